@@ -0,0 +1,50 @@
+//! Checks shared by the fuzz targets
+
+use water_level::{EnvError, Environment, BALANCE_TOLERANCE};
+
+/// Rains for `hours` hours and checks that the solver either rejects the rain, if it is invalid or too much to
+/// keep track of, or places all of it
+pub fn rain(env: &mut Environment, hours: f32) {
+    let rain: f32 = env
+        .columns()
+        .iter()
+        .map(|column| hours * column.width())
+        .sum();
+    let rain_in = env.mass_balance().rain_in + rain;
+    let narrowest = env
+        .columns()
+        .iter()
+        .map(|column| column.width())
+        .fold(f32::MAX, f32::min);
+
+    match env.rain(hours) {
+        Ok(_) => check(env),
+        Err(EnvError::InvalidRain(_)) if !(rain_in / narrowest).is_finite() || hours < 0. => {}
+        Err(err) => panic!("raining for {} hours failed: {}", hours, err),
+    }
+}
+
+/// Checks that no water level is NaN and that the water is conserved
+pub fn check(env: &Environment) {
+    for pos in 1..=env.len() {
+        assert!(
+            !env.water_level(pos).is_nan(),
+            "water level in position {} is NaN",
+            pos
+        );
+        assert!(
+            env.water_depth(pos) >= 0.,
+            "water depth {} in position {} is negative",
+            env.water_depth(pos),
+            pos
+        );
+    }
+
+    let balance = env.mass_balance();
+    assert!(
+        balance.residual().abs() <= BALANCE_TOLERANCE * balance.rain_in.max(1.),
+        "{} units of the {} units of rain are not accounted for",
+        balance.residual(),
+        balance.rain_in
+    );
+}
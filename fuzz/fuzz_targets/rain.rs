@@ -0,0 +1,26 @@
+//! Rains on arbitrary reliefs for an arbitrary number of hours
+//!
+//! Run with `cargo +nightly fuzz run rain` from the root of the repository.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use water_level::{EnvError, Environment};
+
+mod common;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    relief: Vec<u32>,
+    hours: f32,
+}
+
+fuzz_target!(|input: Input| {
+    let mut env = match Environment::new(input.relief) {
+        Ok(env) => env,
+        Err(EnvError::InvalidRelief(_)) => return,
+        Err(err) => panic!("constructing the environment failed: {}", err),
+    };
+    common::rain(&mut env, input.hours);
+});
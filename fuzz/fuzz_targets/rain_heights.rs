@@ -0,0 +1,66 @@
+//! Rains several times on arbitrary real valued reliefs with arbitrary widths and boundaries
+//!
+//! Run with `cargo +nightly fuzz run rain_heights` from the root of the repository.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use water_level::{Boundary, EnvError, Environment};
+
+mod common;
+
+/// Number of rains after which the rest of the input is ignored, to keep every run short
+const MAX_RAINS: usize = 8;
+
+#[derive(Arbitrary, Debug)]
+enum Side {
+    Wall,
+    Open,
+    Height(f32),
+}
+
+impl From<Side> for Boundary {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Wall => Boundary::Wall,
+            Side::Open => Boundary::Open,
+            Side::Height(height) => Boundary::Height(height),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    heights: Vec<f32>,
+    widths: Option<Vec<f32>>,
+    left: Side,
+    right: Side,
+    rains: Vec<f32>,
+}
+
+fuzz_target!(|input: Input| {
+    let Input {
+        heights,
+        widths,
+        left,
+        right,
+        rains,
+    } = input;
+
+    let env = Environment::from_heights(heights)
+        .and_then(|env| match widths {
+            Some(widths) => env.with_widths(widths),
+            None => Ok(env),
+        })
+        .and_then(|env| env.with_boundaries(left.into(), right.into()));
+    let mut env = match env {
+        Ok(env) => env,
+        Err(EnvError::InvalidRelief(_)) => return,
+        Err(err) => panic!("constructing the environment failed: {}", err),
+    };
+
+    for hours in rains.into_iter().take(MAX_RAINS) {
+        common::rain(&mut env, hours);
+    }
+});
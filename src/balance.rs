@@ -0,0 +1,181 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::number::{compensated_sum, Number};
+
+/// Largest residual of the mass balance, relative to the rain, that is still considered as rounding error of `f32`
+pub const BALANCE_TOLERANCE: f32 = 1e-4;
+
+/// Mass balance of an `Environment` or a `Grid`.
+///
+/// Every unit of rain that has fallen on the `Environment` is either stored in one of the `Columns`, left
+/// over because the algorithm could not place it, lost to evaporation and infiltration, or drained over the
+/// sides. The difference between the rain and all of those is the residual, which should be 0 up to the
+/// rounding errors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MassBalance<T = f32> {
+    /// Total units of rain that have fallen on the relief
+    pub rain_in: T,
+    /// Units of water stored in each column of the relief, from left to right
+    pub stored: Vec<T>,
+    /// Units of water the algorithm could not place
+    pub leftover: T,
+    /// Units of water that have evaporated
+    pub evaporated: T,
+    /// Units of water that have infiltrated into the ground
+    pub infiltrated: T,
+    /// Units of water that have left the relief over the left side
+    pub outflow_left: T,
+    /// Units of water that have left the relief over the right side
+    pub outflow_right: T,
+}
+
+impl<T: Number> MassBalance<T> {
+    /// Returns the units of water stored in the whole relief
+    pub fn total_stored(&self) -> T {
+        compensated_sum(self.stored.iter().copied())
+    }
+
+    /// Returns the water that was lost or invented by the algorithm
+    ///
+    /// Positive residual means water has gone missing, negative means water has appeared out of nowhere.
+    pub fn residual(&self) -> T {
+        self.rain_in
+            - self.total_stored()
+            - self.leftover
+            - self.evaporated
+            - self.infiltrated
+            - self.outflow_left
+            - self.outflow_right
+    }
+
+    /// Checks that the residual is within `tolerance` times the rain, or within `tolerance` if less than one unit
+    /// of rain has fallen
+    ///
+    /// Rounding errors grow with the amount of water that is moved around, a fixed tolerance fails large reliefs.
+    pub fn check_relative(&self, tolerance: T) -> Result<(), Imbalance> {
+        self.check(tolerance * self.rain_in.max(T::ONE))
+    }
+
+    /// Checks that the absolute residual is within `tolerance`
+    pub fn check(&self, tolerance: T) -> Result<(), Imbalance> {
+        let residual = self.residual();
+        if residual.abs() > tolerance || !residual.is_finite() {
+            return Err(Imbalance {
+                residual: residual.to_f32(),
+                tolerance: tolerance.to_f32(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Error returned by `MassBalance::check` when the residual is larger than the tolerance
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Imbalance {
+    pub residual: f32,
+    pub tolerance: f32,
+}
+
+impl fmt::Display for Imbalance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "water is not conserved: residual of {} units exceeds the tolerance of {}",
+            self.residual, self.tolerance
+        )
+    }
+}
+
+impl Error for Imbalance {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq as approx_eq;
+
+    #[test]
+    fn test_residual() {
+        let balance = MassBalance {
+            rain_in: 6.,
+            stored: vec![1., 2., 2.5],
+            leftover: 0.25,
+            evaporated: 0.,
+            infiltrated: 0.,
+            outflow_left: 0.,
+            outflow_right: 0.,
+        };
+
+        approx_eq!(balance.total_stored(), 5.5);
+        approx_eq!(balance.residual(), 0.25);
+    }
+
+    #[test]
+    fn test_residual_with_losses() {
+        let balance = MassBalance {
+            rain_in: 6.,
+            stored: vec![1., 2.],
+            leftover: 0.,
+            evaporated: 1.5,
+            infiltrated: 1.,
+            outflow_left: 0.,
+            outflow_right: 0.,
+        };
+
+        approx_eq!(balance.residual(), 0.5);
+    }
+
+    #[test]
+    fn test_residual_with_outflow() {
+        let balance = MassBalance {
+            rain_in: 6.,
+            stored: vec![1., 2.],
+            leftover: 0.,
+            evaporated: 0.,
+            infiltrated: 0.,
+            outflow_left: 1.,
+            outflow_right: 2.,
+        };
+
+        approx_eq!(balance.residual(), 0.);
+    }
+
+    #[test]
+    fn test_check() {
+        let balance = MassBalance {
+            rain_in: 3.,
+            stored: vec![1., 1.5],
+            leftover: 0.,
+            evaporated: 0.,
+            infiltrated: 0.,
+            outflow_left: 0.,
+            outflow_right: 0.,
+        };
+
+        assert!(balance.check(0.5).is_ok());
+        assert_eq!(
+            balance.check(0.1),
+            Err(Imbalance {
+                residual: 0.5,
+                tolerance: 0.1
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_relative() {
+        let balance = MassBalance {
+            rain_in: 3000.,
+            stored: vec![1000., 1999.95],
+            leftover: 0.,
+            evaporated: 0.,
+            infiltrated: 0.,
+            outflow_left: 0.,
+            outflow_right: 0.,
+        };
+
+        assert!(balance.check(1e-3).is_err());
+        assert!(balance.check_relative(1e-4).is_ok());
+        assert!(balance.check_relative(1e-5).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use serde::Serialize;
+
+use crate::env::Column;
+use crate::number::Number;
+
+/// A pond of water standing on the relief
+///
+/// A basin covers a run of neighbouring columns with water on them and is bounded by a dry column or a side
+/// on each end.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Basin<T = f32> {
+    /// Position of the first column covered by the basin
+    pub start: usize,
+    /// Position of the last column covered by the basin
+    pub end: usize,
+    /// Water level of the basin surface
+    pub level: T,
+    /// Units of water stored in the basin
+    pub volume: T,
+    /// Depth of the water above the lowest column of the basin
+    pub max_depth: T,
+    /// The lower of the two rims the basin overflows over
+    pub spill: Spill<T>,
+}
+
+/// The point where a basin overflows once it is full
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Spill<T = f32> {
+    /// Position of the rim, the top of the dry slope next to the basin, 0 or `len() + 1` if it is a side
+    pub pos: usize,
+    /// Water level of the rim, the highest level the basin can reach
+    pub level: T,
+}
+
+impl<T: Number> Basin<T> {
+    /// Constructs the `Basin` covering the positions from `start` up to `end` exclusive
+    ///
+    /// `columns` are all the columns of the `Environment`, including the sides.
+    pub(crate) fn new(columns: &[Column<T>], start: usize, end: usize) -> Self {
+        let covered = &columns[start..end];
+        // The water spreads up the slopes next to the basin, so it only overflows at their tops
+        let mut left = start - 1;
+        while left > 0 && columns[left - 1].water_level() >= columns[left].water_level() {
+            left -= 1;
+        }
+        let mut right = end;
+        while right + 1 < columns.len()
+            && columns[right + 1].water_level() >= columns[right].water_level()
+        {
+            right += 1;
+        }
+        let (left_level, right_level) = (columns[left].water_level(), columns[right].water_level());
+
+        Self {
+            start,
+            end: end - 1,
+            level: covered.iter().map(Column::water_level).fold(T::MIN, T::max),
+            volume: covered.iter().map(Column::volume).sum(),
+            max_depth: covered.iter().map(Column::water).fold(T::ZERO, T::max),
+            spill: if left_level <= right_level {
+                Spill {
+                    pos: left,
+                    level: left_level,
+                }
+            } else {
+                Spill {
+                    pos: right,
+                    level: right_level,
+                }
+            },
+        }
+    }
+
+    /// Returns the number of columns covered by the basin
+    pub fn width(&self) -> usize {
+        self.end - self.start + 1
+    }
+
+    /// Returns how much higher the water can rise before the basin spills
+    pub fn headroom(&self) -> T {
+        (self.spill.level - self.level).max(T::ZERO)
+    }
+
+    /// Returns `true` if the water has reached the spill point
+    pub fn is_full(&self) -> bool {
+        self.headroom() <= T::EPSILON * self.level.abs().max(T::ONE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq as approx_eq;
+
+    fn columns(levels: &[(f32, f32)]) -> Vec<Column> {
+        levels
+            .iter()
+            .map(|(height, water)| {
+                let mut column = Column::new(*height);
+                column.add_water(*water);
+                column
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_new() {
+        let columns = columns(&[(f32::MAX, 0.), (5., 0.), (1., 2.), (2., 1.), (4., 0.)]);
+        let basin = Basin::new(&columns, 2, 4);
+
+        assert_eq!(basin.start, 2);
+        assert_eq!(basin.end, 3);
+        assert_eq!(basin.width(), 2);
+        approx_eq!(basin.level, 3.);
+        approx_eq!(basin.volume, 3.);
+        approx_eq!(basin.max_depth, 2.);
+        assert_eq!(basin.spill.pos, 4);
+        approx_eq!(basin.spill.level, 4.);
+        approx_eq!(basin.headroom(), 1.);
+        assert!(!basin.is_full());
+    }
+
+    #[test]
+    fn test_full() {
+        let columns = columns(&[(3., 0.), (1., 2.), (f32::MAX, 0.)]);
+        let basin = Basin::new(&columns, 1, 2);
+
+        assert_eq!(basin.spill.pos, 0);
+        approx_eq!(basin.headroom(), 0.);
+        assert!(basin.is_full());
+    }
+
+    #[test]
+    fn test_spill_up_the_slope() {
+        // The water spreads onto the columns of 2 and 3 before it overflows the column of 4 into the pit of 0
+        let columns = columns(&[
+            (f32::MAX, 0.),
+            (5., 0.),
+            (1., 1.),
+            (2., 0.),
+            (3., 0.),
+            (4., 0.),
+            (0., 0.),
+            (f32::MAX, 0.),
+        ]);
+        let basin = Basin::new(&columns, 2, 3);
+
+        assert_eq!(basin.spill.pos, 5);
+        approx_eq!(basin.spill.level, 4.);
+        approx_eq!(basin.headroom(), 2.);
+        assert!(!basin.is_full());
+    }
+}
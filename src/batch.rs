@@ -0,0 +1,385 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::env::Environment;
+use crate::error::EnvError;
+
+/// Number of jobs `run_stream` reads and runs at once
+const BATCH_SIZE: usize = 1024;
+
+/// One scenario of a batch, the relief and the hours of rain falling on it
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Name of the scenario, repeated in its `Outcome`
+    pub id: String,
+    /// Heights of the columns, from left to right
+    pub relief: Vec<f32>,
+    /// Hours of rain
+    pub hours: f32,
+}
+
+/// The result of a `Job`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Outcome {
+    pub id: String,
+    /// Water level of every column after the rain, from left to right
+    pub levels: Vec<f32>,
+    /// Residual of the mass balance, see `MassBalance::residual`
+    pub residual: f32,
+}
+
+impl Job {
+    /// Builds the `Environment` of the job and rains on it
+    pub fn run(&self) -> Result<Outcome, EnvError> {
+        let mut env = Environment::from_heights(self.relief.clone())?;
+        env.rain(self.hours)?;
+
+        Ok(Outcome {
+            id: self.id.clone(),
+            levels: env
+                .columns()
+                .iter()
+                .map(|column| column.water_level())
+                .collect(),
+            residual: env.mass_balance().residual(),
+        })
+    }
+}
+
+/// Runs every job on up to `threads` threads and returns the results in the order of the jobs
+///
+/// The threads take the next job as soon as they are done with the last one, so a few long reliefs do not
+/// hold up the rest. A job which fails does not stop the others.
+pub fn run_batch(jobs: &[Job], threads: usize) -> Vec<Result<Outcome, EnvError>> {
+    let next = AtomicUsize::new(0);
+    let mut finished = thread::scope(|scope| {
+        let workers = (0..threads.clamp(1, jobs.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut finished = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        match jobs.get(i) {
+                            Some(job) => finished.push((i, job.run())),
+                            None => return finished,
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("a batch worker has panicked"))
+            .collect::<Vec<(usize, Result<Outcome, EnvError>)>>()
+    });
+
+    finished.sort_by_key(|(i, _)| *i);
+    finished.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Runs every job of `jobs` on up to `threads` threads and writes the outcomes to `out`, in the order of the jobs
+///
+/// The jobs are read and run a chunk at a time, so the input can be much larger than the memory. A job which
+/// fails gets a row with the error, but a job that cannot be read stops the batch.
+pub fn run_stream<W: Write>(
+    jobs: impl Iterator<Item = Result<Job, EnvError>>,
+    out: &mut BatchWriter<W>,
+    threads: usize,
+) -> Result<(), EnvError> {
+    let mut jobs = jobs.peekable();
+    while jobs.peek().is_some() {
+        let chunk = jobs
+            .by_ref()
+            .take(BATCH_SIZE)
+            .collect::<Result<Vec<Job>, EnvError>>()?;
+        for (job, outcome) in chunk.iter().zip(run_batch(&chunk, threads)) {
+            out.write(&job.id, &outcome)?;
+        }
+    }
+    out.flush()
+}
+
+/// Reads the jobs of a batch, one after another
+///
+/// The input is CSV with the columns `id`, `relief` and `hours`, the heights of the relief separated by
+/// spaces, or JSON lines like `{"id": "a", "relief": [3, 1, 2], "hours": 1}`, whichever it starts with.
+pub fn read_jobs<'a>(
+    input: impl Read + 'a,
+) -> Result<Box<dyn Iterator<Item = Result<Job, EnvError>> + 'a>, EnvError> {
+    let mut input = BufReader::new(input);
+    let is_json = input
+        .fill_buf()?
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        == Some(&b'{');
+
+    Ok(if is_json {
+        Box::new(
+            serde_json::Deserializer::from_reader(input)
+                .into_iter::<Job>()
+                .map(|job| Ok(job?)),
+        )
+    } else {
+        Box::new(
+            csv::Reader::from_reader(input)
+                .into_deserialize::<CsvJob>()
+                .map(|row| {
+                    let row = row.map_err(io::Error::from)?;
+                    Ok(Job {
+                        relief: parse_numbers(&row.relief, "a height")?,
+                        id: row.id,
+                        hours: row.hours,
+                    })
+                }),
+        )
+    })
+}
+
+/// Parses a whitespace delimited list of numbers, `expected` says what a number is in the error
+pub fn parse_numbers(input: &str, expected: &'static str) -> Result<Vec<f32>, EnvError> {
+    input
+        .split_whitespace()
+        .map(|number| {
+            number.parse::<f32>().map_err(|_| EnvError::Parse {
+                input: number.to_string(),
+                expected,
+            })
+        })
+        .collect()
+}
+
+/// A job of the batch as it is read from CSV
+#[derive(Deserialize)]
+struct CsvJob {
+    id: String,
+    relief: String,
+    hours: f32,
+}
+
+/// The format of the rows a `BatchWriter` writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchFormat {
+    /// CSV with the columns `id`, `levels`, `residual` and `error`, the levels separated by spaces
+    Csv,
+    /// JSON lines with the fields of the `Outcome`, or the `id` and the `error`
+    Json,
+}
+
+/// Writes one row for the outcome of every job
+pub struct BatchWriter<W: Write> {
+    rows: Rows<W>,
+}
+
+enum Rows<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json(W),
+}
+
+/// A job of the batch as it is written to JSON
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    levels: Option<&'a [f32]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    residual: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<W: Write> BatchWriter<W> {
+    /// Constructs a writer of rows in the `format` to `out`, a CSV writer starts with the header
+    pub fn new(out: W, format: BatchFormat) -> Result<Self, EnvError> {
+        let rows = match format {
+            BatchFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer
+                    .write_record(["id", "levels", "residual", "error"])
+                    .map_err(io::Error::from)?;
+                Rows::Csv(Box::new(writer))
+            }
+            BatchFormat::Json => Rows::Json(out),
+        };
+        Ok(Self { rows })
+    }
+
+    /// Writes the row of the job `id`, with its outcome or its error
+    pub fn write(&mut self, id: &str, outcome: &Result<Outcome, EnvError>) -> Result<(), EnvError> {
+        match &mut self.rows {
+            Rows::Csv(writer) => {
+                let record = match outcome {
+                    Ok(outcome) => {
+                        let levels = outcome
+                            .levels
+                            .iter()
+                            .map(f32::to_string)
+                            .collect::<Vec<String>>();
+                        [
+                            id.to_string(),
+                            levels.join(" "),
+                            outcome.residual.to_string(),
+                            String::new(),
+                        ]
+                    }
+                    Err(err) => [
+                        id.to_string(),
+                        String::new(),
+                        String::new(),
+                        err.to_string(),
+                    ],
+                };
+                writer.write_record(&record).map_err(io::Error::from)?;
+            }
+            Rows::Json(out) => {
+                let row = match outcome {
+                    Ok(outcome) => JsonRow {
+                        id,
+                        levels: Some(&outcome.levels),
+                        residual: Some(outcome.residual),
+                        error: None,
+                    },
+                    Err(err) => JsonRow {
+                        id,
+                        levels: None,
+                        residual: None,
+                        error: Some(err.to_string()),
+                    },
+                };
+                serde_json::to_writer(&mut *out, &row)?;
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the rows written so far to the output
+    pub fn flush(&mut self) -> Result<(), EnvError> {
+        match &mut self.rows {
+            Rows::Csv(writer) => writer.flush()?,
+            Rows::Json(out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: usize, relief: Vec<f32>, hours: f32) -> Job {
+        Job {
+            id: id.to_string(),
+            relief,
+            hours,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let outcome = job(1, vec![3., 1., 2.], 1.).run().unwrap();
+
+        assert_eq!(outcome.id, "1");
+        assert_eq!(outcome.levels, vec![3., 3., 3.]);
+        assert_eq!(outcome.residual, 0.);
+    }
+
+    #[test]
+    fn test_batch_order() {
+        let jobs = (0..200)
+            .map(|i| {
+                job(
+                    i,
+                    vec![(i % 7) as f32, 0., (i % 5) as f32, 1.],
+                    i as f32 / 10.,
+                )
+            })
+            .collect::<Vec<Job>>();
+
+        let sequential = jobs
+            .iter()
+            .map(|job| job.run().unwrap())
+            .collect::<Vec<Outcome>>();
+        let parallel = run_batch(&jobs, 8)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<Outcome>>();
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn test_batch_errors() {
+        let jobs = vec![
+            job(1, vec![], 1.),
+            job(2, vec![1.], -1.),
+            job(3, vec![1.], 1.),
+        ];
+
+        let outcomes = run_batch(&jobs, 2);
+        assert!(matches!(outcomes[0], Err(EnvError::InvalidRelief(_))));
+        assert!(matches!(outcomes[1], Err(EnvError::InvalidRain(_))));
+        assert_eq!(outcomes[2].as_ref().unwrap().levels, vec![2.]);
+        assert!(run_batch(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_read_jobs() {
+        let jobs = vec![
+            Job {
+                id: "a".to_string(),
+                relief: vec![3., 1., 2.],
+                hours: 1.,
+            },
+            Job {
+                id: "b".to_string(),
+                relief: vec![],
+                hours: 0.5,
+            },
+        ];
+        let read = |input: &str| {
+            read_jobs(input.as_bytes())
+                .unwrap()
+                .collect::<Result<Vec<Job>, EnvError>>()
+        };
+
+        assert_eq!(read("id,relief,hours\na,3 1 2,1\nb,,0.5\n").unwrap(), jobs);
+        assert_eq!(
+            read(" {\"id\": \"a\", \"relief\": [3, 1, 2], \"hours\": 1}\n{\"id\": \"b\", \"relief\": [], \"hours\": 0.5}")
+                .unwrap(),
+            jobs
+        );
+        assert!(matches!(
+            read("id,relief,hours\na,3 x 2,1\n"),
+            Err(EnvError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_batch_writer() {
+        let jobs = [job(1, vec![3., 1., 2.], 1.), job(2, vec![1.], -1.)];
+        let error = jobs[1].run().unwrap_err().to_string();
+        let write = |format: BatchFormat| {
+            let mut out = vec![];
+            let mut writer = BatchWriter::new(&mut out, format).unwrap();
+            run_stream(jobs.iter().cloned().map(Ok), &mut writer, 2).unwrap();
+            drop(writer);
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            write(BatchFormat::Csv),
+            format!("id,levels,residual,error\n1,3 3 3,0,\n2,,,\"{}\"\n", error)
+        );
+        assert_eq!(
+            write(BatchFormat::Json),
+            format!(
+                "{{\"id\":\"1\",\"levels\":[3.0,3.0,3.0],\"residual\":0.0}}\n{{\"id\":\"2\",\"error\":\"{}\"}}\n",
+                error
+            )
+        );
+    }
+}
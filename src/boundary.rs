@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::EnvError;
+use crate::number::Number;
+
+/// The side of the relief on the left or the right edge of the `Environment`
+///
+/// In JSON a boundary is written as `"wall"`, `"open"` or `{"height": 2.5}`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Boundary {
+    /// An infinite wall, no water ever leaves the relief over it
+    #[default]
+    Wall,
+    /// A wall of the given height, water spills over it once it reaches the top
+    Height(f32),
+    /// An open edge, water that reaches it drains to nowhere
+    Open,
+}
+
+impl Boundary {
+    /// Returns the height of the column representing the boundary
+    pub(crate) fn height<T: Number>(self) -> T {
+        match self {
+            Boundary::Wall => T::MAX,
+            Boundary::Height(height) => T::from_f32(height),
+            Boundary::Open => T::MIN,
+        }
+    }
+
+    /// Checks that the height of a wall is a finite number `T` can hold exactly
+    pub(crate) fn validate<T: Number>(self) -> Result<(), EnvError> {
+        match self {
+            Boundary::Height(height) if !self.height::<T>().is_finite() => Err(
+                EnvError::InvalidRelief(format!("the height {} of a side is not finite", height)),
+            ),
+            Boundary::Height(height) if self.height::<T>().abs() > T::from_u32(T::MAX_HEIGHT) => {
+                Err(EnvError::InvalidRelief(format!(
+                    "the height {} of a side is further from 0 than {}",
+                    height,
+                    T::MAX_HEIGHT
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! Command line arguments of the binary
+
+use water_level::EnvError;
+
+pub const USAGE: &str = "\
+Usage: water-level [COMMAND] [OPTIONS] [PROFILE]
+
+Rains on a relief and prints where the water settles. Anything not given as an option is asked for on the
+standard input.
+
+Commands:
+  simulate            Rains on the relief and prints the endstate (default)
+  basins              Prints the basins of water standing on the relief after the rain
+  render              Draws the relief and the water
+  batch               Runs every scenario of a CSV or JSON lines input and writes one row of results for each
+  replay              Animates the water rising step by step, in the terminal or as PNG frames
+
+Options:
+  --relief <FILE>     Reads the space delimited heights from FILE, or from the first line of the input if -
+  --scenario <FILE>   Reads the relief, the boundaries and the rain as JSON from FILE, or from the input if -
+  --input <FILE>      Reads the scenarios of batch from FILE instead of the standard input
+  --hours <HOURS>     Hours of rain
+  --profile <FILE>    Reads the rain of every column from FILE instead of raining for some hours
+  --step <HOURS>      Prints the state after every step of HOURS hours (simulate and replay only)
+  --delay <SECONDS>   Shows every frame of replay for SECONDS seconds [default: 0.25]
+  --format <FORMAT>   Output format [default: text]
+                        simulate, basins and batch: text, csv or json
+                        render: text, svg, ansi or png
+                        replay: text or ansi to play in the terminal, png for a frame per file in the
+                        directory of --output, or apng for an animated PNG
+  -o, --output <FILE> Writes the output to FILE instead of the standard output
+  --trace <FILE>      Writes every decision of the flow algorithm as JSON to FILE, for bug reports
+  -q, --quiet         Does not print the prompts and the greetings
+  -h, --help          Prints this help
+";
+
+/// What the binary should do with the `Environment`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Command {
+    Simulate,
+    Basins,
+    Render,
+    Batch,
+    Replay,
+}
+
+const COMMANDS: [(&str, Command); 5] = [
+    ("simulate", Command::Simulate),
+    ("basins", Command::Basins),
+    ("render", Command::Render),
+    ("batch", Command::Batch),
+    ("replay", Command::Replay),
+];
+
+/// How the output is written, by any command
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Format {
+    Text,
+    Csv,
+    Json,
+    Svg,
+    Ansi,
+    Png,
+    Apng,
+}
+
+const FORMATS: [(&str, Format); 7] = [
+    ("text", Format::Text),
+    ("csv", Format::Csv),
+    ("json", Format::Json),
+    ("svg", Format::Svg),
+    ("ansi", Format::Ansi),
+    ("png", Format::Png),
+    ("apng", Format::Apng),
+];
+
+/// The command together with the format of its output, which the command supports
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Simulate(DataFormat),
+    Basins(DataFormat),
+    Render(PictureFormat),
+    Batch(DataFormat),
+    Replay(ReplayFormat),
+}
+
+impl Mode {
+    /// Returns the mode of `command` writing in `format`, or `None` if the command does not support the format
+    fn new(command: Command, format: Format) -> Option<Mode> {
+        Some(match command {
+            Command::Simulate => Mode::Simulate(DataFormat::new(format)?),
+            Command::Basins => Mode::Basins(DataFormat::new(format)?),
+            Command::Render => Mode::Render(PictureFormat::new(format)?),
+            Command::Batch => Mode::Batch(DataFormat::new(format)?),
+            Command::Replay => Mode::Replay(ReplayFormat::new(format)?),
+        })
+    }
+}
+
+/// Formats of simulate, basins and batch
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl DataFormat {
+    fn new(format: Format) -> Option<Self> {
+        match format {
+            Format::Text => Some(DataFormat::Text),
+            Format::Csv => Some(DataFormat::Csv),
+            Format::Json => Some(DataFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Formats of render
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PictureFormat {
+    Text,
+    Svg,
+    Ansi,
+    Png,
+}
+
+impl PictureFormat {
+    fn new(format: Format) -> Option<Self> {
+        match format {
+            Format::Text => Some(PictureFormat::Text),
+            Format::Svg => Some(PictureFormat::Svg),
+            Format::Ansi => Some(PictureFormat::Ansi),
+            Format::Png => Some(PictureFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Formats of replay
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplayFormat {
+    Text,
+    Ansi,
+    Png,
+    Apng,
+}
+
+impl ReplayFormat {
+    fn new(format: Format) -> Option<Self> {
+        match format {
+            Format::Text => Some(ReplayFormat::Text),
+            Format::Ansi => Some(ReplayFormat::Ansi),
+            Format::Png => Some(ReplayFormat::Png),
+            Format::Apng => Some(ReplayFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the name of `value` in a table of names
+fn name<T: PartialEq>(table: &[(&'static str, T)], value: T) -> &'static str {
+    table
+        .iter()
+        .find(|(_, entry)| *entry == value)
+        .map_or("", |(name, _)| name)
+}
+
+/// The parsed command line arguments
+#[derive(Clone, Debug, PartialEq)]
+pub struct Args {
+    pub mode: Mode,
+    pub relief: Option<String>,
+    pub scenario: Option<String>,
+    pub input: Option<String>,
+    pub hours: Option<f32>,
+    pub profile: Option<String>,
+    pub step: Option<f32>,
+    pub delay: Option<f32>,
+    pub output: Option<String>,
+    pub trace: Option<String>,
+    pub quiet: bool,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Simulate(DataFormat::Text),
+            relief: None,
+            scenario: None,
+            input: None,
+            hours: None,
+            profile: None,
+            step: None,
+            delay: None,
+            output: None,
+            trace: None,
+            quiet: false,
+            help: false,
+        }
+    }
+}
+
+/// Parses the command line arguments, without the name of the binary
+///
+/// Options take their value either as the next argument or after `=`, like `--hours=2`.
+pub fn parse<I>(args: I) -> Result<Args, EnvError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut parsed = Args::default();
+    let mut command = Command::Simulate;
+    let mut format = Format::Text;
+    let mut args = args.into_iter();
+    let mut first = true;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.find('=') {
+            Some(pos) if arg.starts_with("--") => {
+                (arg[..pos].to_string(), Some(arg[pos + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| -> Result<String, EnvError> {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| EnvError::Usage(format!("{} needs a value", name)))
+        };
+
+        // Only the first argument can be the command, later ones of the same name are profiles
+        match COMMANDS.iter().find(|(name, _)| *name == flag) {
+            Some((_, name)) if first => {
+                command = *name;
+                first = false;
+                continue;
+            }
+            _ => first = false,
+        }
+
+        match flag.as_str() {
+            "--relief" => parsed.relief = Some(value("--relief")?),
+            "--scenario" => parsed.scenario = Some(value("--scenario")?),
+            "--input" => parsed.input = Some(value("--input")?),
+            "--hours" => parsed.hours = Some(number(&value("--hours")?, "a number of hours")?),
+            "--profile" => parsed.profile = Some(value("--profile")?),
+            "--step" => parsed.step = Some(number(&value("--step")?, "a number of hours")?),
+            "--delay" => parsed.delay = Some(number(&value("--delay")?, "a number of seconds")?),
+            "--format" => {
+                let name = value("--format")?;
+                format = match FORMATS.iter().find(|(entry, _)| *entry == name) {
+                    Some((_, format)) => *format,
+                    None => return Err(EnvError::Usage(format!("unknown format `{}`", name))),
+                }
+            }
+            "--output" | "-o" => parsed.output = Some(value("--output")?),
+            "--trace" => parsed.trace = Some(value("--trace")?),
+            "--quiet" | "-q" => parsed.quiet = true,
+            "--help" | "-h" => parsed.help = true,
+            _ if flag.starts_with('-') && flag != "-" => {
+                return Err(EnvError::Usage(format!("unknown option `{}`", flag)))
+            }
+            // A bare argument after the command is the file of the rain profile, like the value of --profile
+            _ if parsed.profile.is_none() => parsed.profile = Some(arg),
+            _ => return Err(EnvError::Usage(format!("unexpected argument `{}`", arg))),
+        }
+    }
+
+    if parsed.hours.is_some() && parsed.profile.is_some() {
+        return Err(EnvError::Usage(
+            "--hours and --profile cannot be used together".to_string(),
+        ));
+    }
+    if parsed.scenario.is_some()
+        && (parsed.relief.is_some() || parsed.hours.is_some() || parsed.profile.is_some())
+    {
+        return Err(EnvError::Usage(
+            "--scenario cannot be used with --relief, --hours or --profile".to_string(),
+        ));
+    }
+    if command == Command::Batch
+        && (parsed.relief.is_some()
+            || parsed.scenario.is_some()
+            || parsed.hours.is_some()
+            || parsed.profile.is_some())
+    {
+        return Err(EnvError::Usage(
+            "batch reads the reliefs and the hours from the scenarios of the input".to_string(),
+        ));
+    }
+    if parsed.trace.is_some() && command == Command::Batch {
+        return Err(EnvError::Usage(
+            "--trace cannot be used with batch".to_string(),
+        ));
+    }
+    if parsed.input.is_some() && command != Command::Batch {
+        return Err(EnvError::Usage(
+            "--input can only be used with batch".to_string(),
+        ));
+    }
+    parsed.mode = match Mode::new(command, format) {
+        Some(mode) => mode,
+        None => {
+            return Err(EnvError::Usage(format!(
+                "{} does not support the {} format",
+                name(&COMMANDS, command),
+                name(&FORMATS, format)
+            )))
+        }
+    };
+    if matches!(format, Format::Png | Format::Apng) && parsed.output.is_none() {
+        return Err(EnvError::Usage(format!(
+            "the {} format needs --output",
+            name(&FORMATS, format)
+        )));
+    }
+    if parsed.step.is_some() && !matches!(command, Command::Simulate | Command::Replay) {
+        return Err(EnvError::Usage(
+            "--step can only be used with simulate and replay".to_string(),
+        ));
+    }
+    if parsed.delay.is_some() && command != Command::Replay {
+        return Err(EnvError::Usage(
+            "--delay can only be used with replay".to_string(),
+        ));
+    }
+    Ok(parsed)
+}
+
+fn number(input: &str, expected: &'static str) -> Result<f32, EnvError> {
+    input.trim().parse::<f32>().map_err(|_| EnvError::Parse {
+        input: input.to_string(),
+        expected,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, EnvError> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(args(&[]).unwrap(), Args::default());
+    }
+
+    #[test]
+    fn test_options() {
+        let parsed = args(&[
+            "basins",
+            "--relief",
+            "-",
+            "--hours=2.5",
+            "--format",
+            "csv",
+            "-o",
+            "out.csv",
+            "-q",
+        ])
+        .unwrap();
+
+        assert_eq!(parsed.mode, Mode::Basins(DataFormat::Csv));
+        assert_eq!(parsed.relief, Some("-".to_string()));
+        assert_eq!(parsed.hours, Some(2.5));
+        assert_eq!(parsed.output, Some("out.csv".to_string()));
+        assert!(parsed.quiet);
+    }
+
+    #[test]
+    fn test_profile_argument() {
+        assert_eq!(
+            args(&["rain.txt"]).unwrap().profile,
+            Some("rain.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scenario() {
+        let parsed = args(&["basins", "--scenario=-", "--format=json"]).unwrap();
+
+        assert_eq!(parsed.scenario, Some("-".to_string()));
+        assert_eq!(parsed.mode, Mode::Basins(DataFormat::Json));
+    }
+
+    #[test]
+    fn test_batch() {
+        let parsed = args(&["batch", "--input", "jobs.csv", "-q"]).unwrap();
+
+        assert_eq!(parsed.mode, Mode::Batch(DataFormat::Text));
+        assert_eq!(parsed.input, Some("jobs.csv".to_string()));
+    }
+
+    #[test]
+    fn test_replay() {
+        let parsed = args(&[
+            "replay",
+            "--step",
+            "0.5",
+            "--delay=0.1",
+            "--format",
+            "apng",
+            "--output",
+            "a.png",
+        ])
+        .unwrap();
+
+        assert_eq!(parsed.mode, Mode::Replay(ReplayFormat::Apng));
+        assert_eq!(parsed.step, Some(0.5));
+        assert_eq!(parsed.delay, Some(0.1));
+    }
+
+    #[test]
+    fn test_modes() {
+        let mode = |arguments: &[&str]| args(arguments).unwrap().mode;
+
+        assert_eq!(mode(&["render"]), Mode::Render(PictureFormat::Text));
+        assert_eq!(
+            mode(&["render", "--format=png", "-o", "a.png"]),
+            Mode::Render(PictureFormat::Png)
+        );
+        assert_eq!(
+            mode(&["replay", "--format=ansi"]),
+            Mode::Replay(ReplayFormat::Ansi)
+        );
+        assert_eq!(mode(&["--format=json"]), Mode::Simulate(DataFormat::Json));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(matches!(args(&["--hours"]), Err(EnvError::Usage(_))));
+        assert!(matches!(
+            args(&["--hours", "x"]),
+            Err(EnvError::Parse { .. })
+        ));
+        assert!(matches!(
+            args(&["--format", "xml"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(args(&["--color"]), Err(EnvError::Usage(_))));
+        assert!(matches!(
+            args(&["render", "--format", "csv"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["basins", "--format", "svg"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["simulate", "--format", "ansi"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["replay", "--format", "png"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["render", "--delay", "1"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["batch", "--trace", "trace.json"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["render", "--step", "1"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["--hours", "1", "--profile", "rain.txt"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["--scenario", "storm.json", "--hours", "1"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["batch", "--hours", "1"]),
+            Err(EnvError::Usage(_))
+        ));
+        assert!(matches!(
+            args(&["--input", "jobs.csv"]),
+            Err(EnvError::Usage(_))
+        ));
+    }
+}
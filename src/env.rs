@@ -2,93 +2,827 @@ use std::f32;
 use std::fmt;
 use std::ops::Sub;
 
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::balance::MassBalance;
+use crate::basin::Basin;
+use crate::boundary::Boundary;
+use crate::error::EnvError;
+use crate::events::{Event, EventLog};
+use crate::losses::Losses;
+use crate::number::{compensated_sum, Number};
+use crate::render;
+use crate::schedule::RainSchedule;
+use crate::trace::{Action, Handler, Resume, Trace, TraceStep};
+
+/// Hours of rain after which an inverse query gives up on reaching its target
+const MAX_RAIN_HOURS: f32 = (1 << f32::MANTISSA_DIGITS) as f32;
+
+/// Relative tolerance within which an inverse query has reached its target
+const TARGET_TOLERANCE: f32 = 1e-5;
+
+/// Number of times an inverse query narrows down the rain before it gives up on reaching its target
+const MAX_ITERATIONS: usize = 64;
+
 /// Environment is the center structure of the program.
 ///
 /// It stores the current state of the program. The Environment consists of a vector of n `Columns`
-/// which represent the relief + 2 infinite walls on each side and a rain bank of n-2 values.
+/// which represent the relief + 2 sides and a rain bank of n-2 values. The sides are infinite walls unless
+/// other `Boundaries` are set.
 ///
 /// When it rains for every hour rain bank fills up by 1 unit of rain.
+///
+/// The heights and the water are stored as `f32` by default. Any other `Number` can be used instead, for
+/// example `f64` for long reliefs or `Rational` for results which are exact to the bit.
 #[derive(Debug)]
-pub struct Environment {
-    columns: Vec<Column>,
-    rain: Vec<f32>,
+pub struct Environment<T = f32> {
+    columns: Vec<Column<T>>,
+    rain: Vec<T>,
+    /// Skip list over the rain bank. Every field points to itself or to a later field, and all the
+    /// fields in between are known to be drained.
+    next_rain: Vec<usize>,
+    /// Skip list over the columns towards the left. Every column points to itself or to an earlier column, and all
+    /// the columns in between are known not to be lower than the column before them.
+    descents: Vec<usize>,
+    /// Total units of rain that have fallen on the relief
+    rain_in: T,
+    /// Total units of water the algorithm could not place
+    leftover: T,
+    /// How water leaves the relief over time, if it does
+    losses: Option<Losses>,
+    /// Total units of water that have evaporated
+    evaporated: T,
+    /// Total units of water that have infiltrated into the ground
+    infiltrated: T,
+    /// The left and the right side of the relief
+    boundaries: (Boundary, Boundary),
+    /// Total units of water that have left the relief over the left and the right side
+    outflow: (T, T),
+    /// The decisions of the flow algorithm, recorded only while tracing is on
+    trace: Option<Trace>,
+    /// The columns of the pools that have filled up to a rim during the current rain, `None` for all the others
+    full: Vec<Option<FullPool>>,
 }
 
-impl Environment {
-    /// Constructs a new `Environment`
-    pub fn new(columns: Vec<u32>) -> Self {
+impl<T: Clone> Clone for Environment<T> {
+    fn clone(&self) -> Self {
+        Self {
+            trace: self.trace.clone(),
+            ..self.untraced()
+        }
+    }
+
+    /// Copies the state of `source`, reusing the memory of the columns and the rain bank
+    fn clone_from(&mut self, source: &Self) {
+        self.clone_untraced_from(source);
+        self.trace = source.trace.clone();
+    }
+}
+
+impl<T: Clone> Environment<T> {
+    /// Returns a copy of the state without the trace, for rains which are only tried out
+    pub(crate) fn untraced(&self) -> Self {
         Self {
-            rain: vec![0.; columns.len()],
+            columns: self.columns.clone(),
+            rain: self.rain.clone(),
+            next_rain: self.next_rain.clone(),
+            descents: self.descents.clone(),
+            rain_in: self.rain_in.clone(),
+            leftover: self.leftover.clone(),
+            losses: self.losses.clone(),
+            evaporated: self.evaporated.clone(),
+            infiltrated: self.infiltrated.clone(),
+            boundaries: self.boundaries,
+            outflow: self.outflow.clone(),
+            trace: None,
+            full: self.full.clone(),
+        }
+    }
+
+    /// Copies the state of `source` like `clone_from`, but leaves its trace behind and stops tracing
+    pub(crate) fn clone_untraced_from(&mut self, source: &Self) {
+        let Self {
+            columns,
+            rain,
+            next_rain,
+            descents,
+            rain_in,
+            leftover,
+            losses,
+            evaporated,
+            infiltrated,
+            boundaries,
+            outflow,
+            trace: _,
+            full,
+        } = source;
+        self.columns.clone_from(columns);
+        self.rain.clone_from(rain);
+        self.next_rain.clone_from(next_rain);
+        self.descents.clone_from(descents);
+        self.rain_in = rain_in.clone();
+        self.leftover = leftover.clone();
+        self.losses = losses.clone();
+        self.evaporated = evaporated.clone();
+        self.infiltrated = infiltrated.clone();
+        self.boundaries = *boundaries;
+        self.outflow = outflow.clone();
+        self.trace = None;
+        self.full.clone_from(full);
+    }
+}
+
+impl Environment {
+    /// Constructs a new `Environment` which stores the heights and the water as `f32`
+    ///
+    /// Fails if the relief has no columns or if a height is too large to be stored exactly.
+    pub fn new(columns: Vec<u32>) -> Result<Self, EnvError> {
+        Self::from_relief(columns)
+    }
+}
+
+impl<T: Number> Environment<T> {
+    /// Constructs a new `Environment` with any `Number` type, e.g. `Environment::<f64>::from_relief`
+    ///
+    /// Fails if the relief has no columns or if a height is too large to be stored exactly.
+    pub fn from_relief(columns: Vec<u32>) -> Result<Self, EnvError> {
+        if let Some(pos) = columns.iter().position(|height| *height > T::MAX_HEIGHT) {
+            return Err(EnvError::InvalidRelief(format!(
+                "height {} of column {} is larger than {}",
+                columns[pos],
+                pos + 1,
+                T::MAX_HEIGHT
+            )));
+        }
+
+        Self::from_heights(columns.into_iter().map(T::from_u32).collect())
+    }
+
+    /// Constructs a new `Environment` from real valued heights, which may also be negative
+    ///
+    /// Fails if the relief has no columns, if a height is not a finite number or if it is further from 0 than
+    /// `MAX_HEIGHT`, beyond which the water levels on top of it are not stored closely enough to compare.
+    pub fn from_heights(columns: Vec<T>) -> Result<Self, EnvError> {
+        if columns.is_empty() {
+            return Err(EnvError::InvalidRelief(
+                "the relief has no columns".to_string(),
+            ));
+        }
+        if let Some(pos) = columns.iter().position(|height| !height.is_finite()) {
+            return Err(EnvError::InvalidRelief(format!(
+                "height {} of column {} is not a finite number",
+                columns[pos],
+                pos + 1
+            )));
+        }
+        let max = T::from_u32(T::MAX_HEIGHT);
+        if let Some(pos) = columns.iter().position(|height| height.abs() > max) {
+            return Err(EnvError::InvalidRelief(format!(
+                "height {} of column {} is further from 0 than {}",
+                columns[pos],
+                pos + 1,
+                T::MAX_HEIGHT
+            )));
+        }
+
+        Ok(Self {
+            rain: vec![T::ZERO; columns.len()],
+            next_rain: (0..=columns.len()).collect(),
+            descents: (0..columns.len() + 2).collect(),
+            rain_in: T::ZERO,
+            leftover: T::ZERO,
+            losses: None,
+            evaporated: T::ZERO,
+            infiltrated: T::ZERO,
+            boundaries: (Boundary::Wall, Boundary::Wall),
+            outflow: (T::ZERO, T::ZERO),
+            trace: None,
+            full: vec![None; columns.len() + 2],
             columns: columns
-                .iter()
-                .map(|height| Column::new(*height as f32))
-                .collect::<Vec<Column>>(),
+                .into_iter()
+                .map(Column::new)
+                .collect::<Vec<Column<T>>>(),
         }
-        .add_sides()
+        .add_sides())
     }
 
     /// Adds Infinite Sides to the start and end of the array
     fn add_sides(mut self) -> Self {
-        let mut columns = vec![Column::new(f32::MAX)];
+        let mut columns = vec![Column::new(T::MAX)];
         columns.append(&mut self.columns);
-        columns.push(Column::new(f32::MAX));
+        columns.push(Column::new(T::MAX));
 
         self.columns = columns;
 
         self
     }
 
+    /// Sets the width of every column, from left to right
+    ///
+    /// A column catches rain and stores water in proportion to its width. Fails if there is not exactly one
+    /// width for every column, if a width is not a finite, positive number or if the widths add up to more than `T`
+    /// can hold.
+    pub fn with_widths(mut self, widths: Vec<T>) -> Result<Self, EnvError> {
+        if widths.len() != self.len() {
+            return Err(EnvError::InvalidRelief(format!(
+                "expected {} widths, one for each column, found {}",
+                self.len(),
+                widths.len()
+            )));
+        }
+        if let Some(pos) = widths
+            .iter()
+            .position(|width| !width.is_finite() || *width <= T::ZERO)
+        {
+            return Err(EnvError::InvalidRelief(format!(
+                "width {} of column {} is not a finite, positive number",
+                widths[pos],
+                pos + 1
+            )));
+        }
+        let total = widths.iter().copied().sum::<T>();
+        if !total.is_finite() {
+            // A plateau this wide would spread any water into nothing
+            return Err(EnvError::InvalidRelief(format!(
+                "widths add up to {}, which is not a finite number",
+                total
+            )));
+        }
+
+        for (column, width) in self.columns[1..].iter_mut().zip(widths) {
+            column.width = width;
+        }
+        Ok(self)
+    }
+
+    /// Replaces the infinite sides with the `left` and `right` boundaries
+    ///
+    /// Fails if the height of a wall is not finite or further from 0 than `MAX_HEIGHT`.
+    pub fn with_boundaries(mut self, left: Boundary, right: Boundary) -> Result<Self, EnvError> {
+        left.validate::<T>()?;
+        right.validate::<T>()?;
+
+        let last = self.columns.len() - 1;
+        self.columns[0] = Column::new(left.height());
+        self.columns[last] = Column::new(right.height());
+        self.boundaries = (left, right);
+
+        Ok(self)
+    }
+
+    /// Returns the left and the right boundary of the relief
+    pub fn boundaries(&self) -> (Boundary, Boundary) {
+        self.boundaries
+    }
+
+    /// Returns the units of water that have left the relief over the left and the right side
+    pub fn outflow(&self) -> (T, T) {
+        self.outflow
+    }
+
+    /// Starts recording the decisions of the flow algorithm in a new `Trace`, dropping the old one
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::default());
+    }
+
+    /// Stops recording the decisions of the flow algorithm and returns the `Trace`, if it was recording
+    pub fn stop_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// Returns the `Trace` recorded so far, if tracing is on
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// Returns the number of columns in the relief
+    pub fn len(&self) -> usize {
+        self.columns.len() - 2
+    }
+
+    /// Returns `true` if the relief has no columns
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the columns of the relief, without the infinite sides
+    pub fn columns(&self) -> &[Column<T>] {
+        &self.columns[1..self.columns.len() - 1]
+    }
+
     /// Returns the water level of the columns in position `pos`
-    #[allow(dead_code)]
-    pub fn water_level(&self, pos: usize) -> f32 {
+    ///
+    /// Positions start with 1 for the first column of the relief and end with `len()`. Positions 0 and
+    /// `len() + 1` are the infinite sides.
+    pub fn water_level(&self, pos: usize) -> T {
         self.columns[pos].water_level()
     }
 
+    /// Returns the depth of the water above the column in position `pos`
+    ///
+    /// Positions are the same as in `water_level`.
+    pub fn water_depth(&self, pos: usize) -> T {
+        self.columns[pos].water()
+    }
+
+    /// Returns the units of water stored on the column in position `pos`, its depth times its width
+    ///
+    /// Positions are the same as in `water_level`.
+    pub fn water_volume(&self, pos: usize) -> T {
+        self.columns[pos].volume()
+    }
+
     /// Accepts the number of hours it has rain and mutate the environment to its endstate.
     ///
     /// Main Public method of the `Environment`. Calling this method will simulate `rain_hours` hours of rain that
     /// has fallen on the `Environment`.
     ///
-    /// It will return remaining water. That value should be 0 if algorithm worked correctly.
-    pub fn rain(&mut self, rain_hours: f32) -> f32 {
-        self.rain = vec![rain_hours; self.columns.len() - 2];
+    /// It will return the water of this rain that could not be placed. That value should be 0 if algorithm worked
+    /// correctly. See `mass_balance` for the balance of all the rain so far.
+    ///
+    /// The levels are those of the first, recursive version of the algorithm, except where full pools meet rims of the
+    /// same height. There the water now spills evenly over the row of full pools, and the rounding residue of a pool
+    /// no longer runs off as backwater, which changes the levels on walled reliefs too.
+    ///
+    /// Fails if `rain_hours` is negative or not finite, if all the rain so far would be too deep for `T` to hold on the
+    /// narrowest column, or if the algorithm reaches a relief it does not handle.
+    pub fn rain(&mut self, rain_hours: T) -> Result<T, EnvError> {
+        self.rain_profile(&vec![rain_hours; self.len()])
+    }
+
+    /// Rains like `rain` and returns the `Events` of the flood, stamped with the hours since the start of this rain
+    ///
+    /// The onsets of the events are found in `f32` hours, the endstate is the same as after `rain`. See
+    /// `mass_balance` for the water that could not be placed.
+    pub fn rain_with_events(&mut self, rain_hours: T) -> Result<Vec<Event>, EnvError> {
+        let before = self.clone();
+        let mut log = EventLog::new(self);
+        self.rain(rain_hours)?;
+        log.record(&before, self, 0., rain_hours.to_f32(), |env, hours| {
+            env.rain(T::from_f32(hours)).map(|_| ())
+        })?;
+        Ok(log.into_events())
+    }
 
-        let mut backwater = self.flow(1, 0.);
-        while backwater > 0. {
-            backwater = self.flow(1, backwater);
+    /// Rains a different amount of water on every column and mutates the environment to its endstate.
+    ///
+    /// The column in position `pos` gets `profile[pos - 1]` units of rain, so the profile needs exactly one
+    /// value for every column of the relief. Otherwise it works the same as `rain`.
+    pub fn rain_profile(&mut self, profile: &[T]) -> Result<T, EnvError> {
+        if profile.len() != self.len() {
+            return Err(EnvError::ProfileLength {
+                expected: self.len(),
+                found: profile.len(),
+            });
+        }
+        if let Some(rain) = profile
+            .iter()
+            .find(|rain| !rain.is_finite() || **rain < T::ZERO)
+        {
+            return Err(EnvError::InvalidRain(rain.to_f32()));
+        }
+
+        // Every column catches the rain falling on its whole width
+        let rain: Vec<T> = profile
+            .iter()
+            .zip(&self.columns[1..])
+            .map(|(rain, column)| *rain * column.width)
+            .collect();
+        let rain_in = self.rain_in + compensated_sum(rain.iter().copied());
+        let narrowest = self
+            .columns()
+            .iter()
+            .fold(T::MAX, |width, column| width.min(column.width));
+        if !(rain_in / narrowest).is_finite() {
+            // Too much rain in total to keep track of, should it all run onto the narrowest column
+            return Err(EnvError::InvalidRain(rain_in.to_f32()));
         }
+        self.rain = rain;
+        self.rain_in = rain_in;
+        self.full.clear();
+        self.full.resize(self.columns.len(), None);
+
+        // The skip lists stay valid for the whole rain, as the rain bank only drains and a column only stops being
+        // lower than the column before it when it fills up
+        let bank = self.rain.len();
+        for (field, rain_water) in self.rain.iter().enumerate() {
+            self.next_rain[field] = if *rain_water == T::ZERO {
+                field + 1
+            } else {
+                field
+            };
+        }
+        self.next_rain[bank] = bank;
+        self.descents[0] = 0;
+        for pos in 1..self.columns.len() {
+            self.descents[pos] = if self.columns[pos - 1] > self.columns[pos] {
+                pos
+            } else {
+                pos - 1
+            };
+        }
+
+        let leftover = self.leftover;
+        let mut backwater = self.flow(1, T::ZERO)?;
+        loop {
+            // Backwater of the first column spills over the left side once it is not higher than the column
+            if backwater > T::ZERO && self.columns[0] <= self.columns[1] {
+                self.outflow.0 += backwater;
+                self.record(
+                    Handler::LeftSide,
+                    0,
+                    backwater,
+                    Action::Spill {
+                        water: backwater.to_f32(),
+                    },
+                );
+                backwater = T::ZERO;
+            }
 
-        return 0.;
+            if backwater > T::ZERO {
+                backwater = self.flow(1, backwater)?;
+            } else if self.next_rain_pos(1) < self.columns.len() - 1 {
+                // The backwater has returned before the walk reached the end of the rain bank, the walk resumes by
+                // jumping straight to the rain that is left
+                backwater = self.flow(1, T::ZERO)?;
+            } else {
+                break;
+            }
+        }
+
+        // Anything still in the rain bank has never reached the relief
+        for pos in 1..self.columns.len() - 1 {
+            let rain_water = self.new_rain(pos);
+            self.leftover += rain_water;
+        }
+
+        Ok(self.leftover - leftover)
+    }
+
+    /// Rains `rain(pos)` units of water on the column in position `pos` and mutates the environment to its endstate.
+    ///
+    /// A spatial version of `rain_profile`, positions are the same as in `water_level`.
+    pub fn rain_with<F>(&mut self, rain: F) -> Result<T, EnvError>
+    where
+        F: FnMut(usize) -> T,
+    {
+        let profile = (1..=self.len()).map(rain).collect::<Vec<T>>();
+        self.rain_profile(&profile)
+    }
+
+    /// Rains according to the `schedule` and mutates the environment to its endstate.
+    ///
+    /// The segments of the schedule rain one after another. Use `Simulation::run_schedule` to see the
+    /// intermediate states as well. Returns the water of the whole schedule that could not be placed.
+    pub fn rain_schedule(&mut self, schedule: &RainSchedule) -> Result<T, EnvError> {
+        schedule.validate(self.len())?;
+
+        let mut leftover = T::ZERO;
+        for segment in schedule.segments() {
+            let profile = segment
+                .intensity
+                .profile(segment.duration, self.len())
+                .into_iter()
+                .map(T::from_f32)
+                .collect::<Vec<T>>();
+            leftover += self.rain_profile(&profile)?;
+        }
+        Ok(leftover)
+    }
+
+    /// Sets how water leaves the `Environment` over time
+    ///
+    /// The losses are only applied by `apply_losses` and by the steps of a `Simulation`, which lowers the water
+    /// after the rain of every step. `rain`, `rain_profile` and `rain_schedule` only ever add water, whatever the
+    /// losses, so the same rain gives the same endstate with and without them.
+    ///
+    /// Fails if a rate is negative or not finite, or if the infiltration rates do not fit the relief.
+    pub fn set_losses(&mut self, losses: Losses) -> Result<(), EnvError> {
+        losses.validate(self.len())?;
+        self.losses = Some(losses);
+        Ok(())
+    }
+
+    /// Returns how water leaves the `Environment` over time, if it does
+    pub fn losses(&self) -> Option<&Losses> {
+        self.losses.as_ref()
+    }
+
+    /// Lets `hours` hours pass without rain, applying the losses to the water on the relief.
+    ///
+    /// Every pond loses the evaporation and the infiltration of all the columns it covers, and its surface
+    /// drops evenly. Returns the units of water that have left the relief.
+    pub fn apply_losses(&mut self, hours: f32) -> Result<T, EnvError> {
+        if !hours.is_finite() || hours < 0. {
+            return Err(EnvError::InvalidStep(hours));
+        }
+        let losses = match self.losses.take() {
+            Some(losses) => losses,
+            None => return Ok(T::ZERO),
+        };
+
+        let mut lost = T::ZERO;
+        for (start, end) in self.ponds() {
+            lost += self.lower_pond(start, end, &losses, hours);
+        }
+
+        self.losses = Some(losses);
+        Ok(lost)
+    }
+
+    /// Returns all the basins of water standing on the relief, from left to right
+    pub fn basins(&self) -> Vec<Basin<T>> {
+        self.ponds()
+            .into_iter()
+            .map(|(start, end)| Basin::new(&self.columns, start, end))
+            .collect()
+    }
+
+    /// Returns the hours of uniform rain it takes until the water level in position `pos` reaches `level`
+    ///
+    /// The rain falls on the current state of the `Environment`, which is left unchanged. Returns 0 if the
+    /// level is already reached. Fails if the level is never reached, for example because the water drains
+    /// over an open side first.
+    pub fn hours_to_level(&self, pos: usize, level: T) -> Result<T, EnvError> {
+        self.hours_until(level, |env| env.water_level(pos))
+    }
+
+    /// Returns the hours of uniform rain it takes until the basin over position `pos` holds `volume` units of
+    /// water
+    ///
+    /// The basin is the one covering `pos` after the rain, so it may have merged with its neighbours on the
+    /// way. Otherwise the same as `hours_to_level`.
+    pub fn hours_to_volume(&self, pos: usize, volume: T) -> Result<T, EnvError> {
+        self.hours_until(volume, |env| {
+            env.basins()
+                .iter()
+                .find(|basin| basin.start <= pos && pos <= basin.end)
+                .map_or(T::ZERO, |basin| basin.volume)
+        })
+    }
+
+    /// Returns the hours of uniform rain it takes until `measure` of the `Environment` reaches `target`
+    ///
+    /// As long as the same columns are flooded, the water rises linearly with the rain. So the hours are
+    /// interpolated between two rains on both sides of the target, which is exact unless a column starts
+    /// flooding in between. In that case the interpolated rain misses the target and the interval between
+    /// the two rains is halved before interpolating again.
+    ///
+    /// If the measure jumps past the target, as the volume of a basin does when it merges with its neighbour,
+    /// returns the hours at which it jumps. Fails if the rain is not narrowed down within `MAX_ITERATIONS`.
+    fn hours_until<F>(&self, target: T, measure: F) -> Result<T, EnvError>
+    where
+        F: Fn(&Environment<T>) -> T,
+    {
+        if !target.is_finite() {
+            return Err(EnvError::InvalidTarget(target.to_f32()));
+        }
+        // Every rain starts over from the current state, in the memory of the same copy
+        let mut scratch = self.clone();
+        let mut after = |hours: T| -> Result<T, EnvError> {
+            scratch.clone_from(self);
+            scratch.rain(hours)?;
+            Ok(measure(&scratch))
+        };
+        let tolerance = T::from_f32(TARGET_TOLERANCE) * target.abs().max(T::ONE);
+
+        let (mut low, mut low_value) = (T::ZERO, measure(self));
+        if low_value >= target - tolerance {
+            return Ok(T::ZERO);
+        }
+
+        // Double the rain until the target is passed
+        let (mut high, mut high_value) = (T::ONE, after(T::ONE)?);
+        while high_value < target - tolerance {
+            if high >= T::from_f32(MAX_RAIN_HOURS) {
+                return Err(EnvError::Unreachable(target.to_f32()));
+            }
+            (low, low_value) = (high, high_value);
+            high += high;
+            high_value = after(high)?;
+        }
+        if high_value <= target + tolerance {
+            return Ok(high);
+        }
+
+        for _ in 0..MAX_ITERATIONS {
+            let interpolated = low + (target - low_value) * (high - low) / (high_value - low_value);
+            for hours in [interpolated, (low + high) / T::from_u32(2)] {
+                let value = after(hours)?;
+                if (value - target).abs() <= tolerance {
+                    return Ok(hours);
+                }
+                if value < target {
+                    (low, low_value) = (hours, value);
+                } else {
+                    (high, high_value) = (hours, value);
+                }
+            }
+            if high - low <= T::EPSILON * high {
+                // The measure jumps at `high`
+                return Ok(high);
+            }
+        }
+        Err(EnvError::NotConverged(target.to_f32()))
+    }
+
+    /// Returns the positions of all the runs of neighbouring columns with water on them
+    ///
+    /// Every run is returned as a pair of its first position and the position after its last one.
+    fn ponds(&self) -> Vec<(usize, usize)> {
+        let mut ponds = vec![];
+        let mut pos = 1;
+        while pos < self.columns.len() - 1 {
+            if self.columns[pos].water <= T::ZERO {
+                pos += 1;
+                continue;
+            }
+
+            let start = pos;
+            while pos < self.columns.len() - 1 && self.columns[pos].water > T::ZERO {
+                pos += 1;
+            }
+            ponds.push((start, pos));
+        }
+        ponds
+    }
+
+    /// Applies `hours` hours of losses to the pond covering the columns from `start` up to `end`
+    ///
+    /// The water surface of the pond drops evenly until the pond has lost all its losses. Returns the units
+    /// of water that have left the pond.
+    fn lower_pond(&mut self, start: usize, end: usize, losses: &Losses, hours: f32) -> T {
+        let volume: T = self.columns[start..end].iter().map(Column::volume).sum();
+        let area: T = self.columns[start..end].iter().map(Column::width).sum();
+        let evaporation = T::from_f32(losses.evaporation * hours) * area;
+        let infiltration: T = (start..end)
+            .map(|pos| T::from_f32(losses.infiltration(pos) * hours) * self.columns[pos].width)
+            .sum();
+
+        let lost = (evaporation + infiltration).min(volume);
+        if lost <= T::ZERO {
+            return T::ZERO;
+        }
+        let share = lost / (evaporation + infiltration);
+        self.evaporated += evaporation * share;
+        self.infiltrated += infiltration * share;
+
+        // Find the level which holds the remaining water, filling the lowest columns first
+        let mut columns = self.columns[start..end].to_vec();
+        columns.sort_by(|a, b| {
+            a.height
+                .partial_cmp(&b.height)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let remaining = volume - lost;
+        let mut level = columns[0].height;
+        let mut below = T::ZERO;
+        let mut area = T::ZERO;
+        for (count, column) in columns.iter().enumerate() {
+            below += column.height * column.width;
+            area += column.width;
+            level = (remaining + below) / area;
+            if count + 1 == columns.len() || level <= columns[count + 1].height {
+                break;
+            }
+        }
+
+        for column in &mut self.columns[start..end] {
+            column.water = (level - column.height).max(T::ZERO);
+            column.level = if column.water > T::ZERO {
+                level
+            } else {
+                column.height
+            };
+        }
+
+        lost
+    }
+
+    /// Returns the mass balance of all the rain that has fallen on the `Environment`
+    pub fn mass_balance(&self) -> MassBalance<T> {
+        MassBalance {
+            rain_in: self.rain_in,
+            stored: self.columns().iter().map(Column::volume).collect(),
+            leftover: self.leftover,
+            evaporated: self.evaporated,
+            infiltrated: self.infiltrated,
+            outflow_left: self.outflow.0,
+            outflow_right: self.outflow.1,
+        }
     }
 
     /// Grabs the rain from the rain bank in the environemnt
     ///
     /// Will drain the bank if used. After that calling `new_rain` for the same field will
     /// reutrn 0
-    fn new_rain(&mut self, curr_pos: usize) -> f32 {
+    fn new_rain(&mut self, curr_pos: usize) -> T {
         let rain_water = self.rain[curr_pos - 1];
-        if rain_water != 0. {
-            self.rain[curr_pos - 1] = 0.;
+        if rain_water != T::ZERO {
+            self.rain[curr_pos - 1] = T::ZERO;
         }
+        self.next_rain[curr_pos - 1] = curr_pos;
         rain_water
     }
 
-    /// The entry point for the recursive algorithm. It asks `rain_water` units of water to flow into the colums at position `curr_pos`
+    /// Returns the first position from `curr_pos` onwards which still has rain in the rain bank
     ///
-    /// This function is the main recursive functin and it is used to calculate the end distrubtion of the water in colums located
-    /// at `curr_pos` if `rain_water` units of water it has falled on it.
+    /// Walking forward without any rain water does not change the environment until it reaches such
+    /// position, so the walk can jump straight to it. Returns the position of the right side if the
+    /// whole bank is drained.
+    fn next_rain_pos(&mut self, curr_pos: usize) -> usize {
+        let mut field = curr_pos - 1;
+        while self.next_rain[field] != field {
+            field = self.next_rain[field];
+        }
+
+        // Point every visited field straight to the result to keep the next walks short
+        let mut visited = curr_pos - 1;
+        while self.next_rain[visited] != field {
+            visited = std::mem::replace(&mut self.next_rain[visited], field);
+        }
+
+        field + 1
+    }
+
+    /// The entry point for the flow algorithm. It asks `rain_water` units of water to flow into the colums at position `curr_pos`
+    ///
+    /// This function is used to calculate the end distrubtion of the water in colums located at `curr_pos` if `rain_water`
+    /// units of water it has falled on it. It returns the backwater that could not be placed.
+    ///
+    /// The handle methods never call each other directly. Each of them returns the next `Step` and this function executes
+    /// it, keeping the pending work on an explicit stack. That way the call stack stays flat no matter how long the relief is.
+    fn flow(&mut self, curr_pos: usize, rain_water: T) -> Result<T, EnvError> {
+        let mut stack = Vec::new();
+        let mut step = Step::Flow(curr_pos, rain_water);
+
+        loop {
+            step = match step {
+                Step::Flow(pos, water) => self.flow_step(pos, water)?,
+                Step::Call(pos, water, then) => {
+                    stack.push(then);
+                    Step::Flow(pos, water)
+                }
+                Step::Return(backwater) => match stack.pop() {
+                    None => return Ok(backwater),
+                    Some(Then::Add(water)) => Step::Return(water + backwater),
+                    Some(Then::Flow(pos)) => Step::Flow(pos, backwater),
+                    Some(Then::FlowIfAny(pos)) => {
+                        if backwater > T::ZERO {
+                            Step::Flow(pos, backwater)
+                        } else {
+                            Step::Return(T::ZERO)
+                        }
+                    }
+                    Some(Then::RunBack(start, end)) => {
+                        let pos = self.last_descent(end);
+                        if backwater > T::ZERO && pos >= start {
+                            Step::Call(pos, backwater, Then::RunBack(start, pos))
+                        } else {
+                            Step::Return(backwater)
+                        }
+                    }
+                },
+            };
+        }
+    }
+
+    /// A single step of the flow algorithm at position `curr_pos`
     ///
     /// It calls the correct handle method, depending on the topology of the local relief.
-    fn flow(&mut self, curr_pos: usize, mut rain_water: f32) -> f32 {
-        // println!("FLOW {} {}", curr_pos, rain_water);
+    fn flow_step(&mut self, curr_pos: usize, mut rain_water: T) -> Result<Step<T>, EnvError> {
         if curr_pos >= self.columns.len() - 1 {
-            return rain_water;
+            // Water reaching the right side spills over it once it is not higher than the last column
+            if rain_water > T::ZERO && self.columns[curr_pos] <= self.columns[curr_pos - 1] {
+                self.outflow.1 += rain_water;
+                self.record(
+                    Handler::RightSide,
+                    curr_pos,
+                    rain_water,
+                    Action::Spill {
+                        water: rain_water.to_f32(),
+                    },
+                );
+                return Ok(Step::Return(T::ZERO));
+            }
+            return Ok(self.traced(
+                Handler::RightSide,
+                curr_pos,
+                rain_water,
+                Step::Return(rain_water),
+            ));
         }
 
-        // Update rain water and walk forward if there is no rainwater
+        // Update rain water. Water too small to place is left over, and without any water the walk jumps over the
+        // drained columns to the next rain.
         rain_water += self.new_rain(curr_pos);
-        if rain_water < f32::EPSILON {
-            return self.flow(curr_pos + 1, 0.);
+        if rain_water <= T::ZERO || rain_water < T::EPSILON {
+            self.leftover += rain_water;
+            rain_water = T::ZERO;
+            if let Some(step) = self.jump_to_rain(curr_pos) {
+                return Ok(self.traced(Handler::Dry, curr_pos, rain_water, step));
+            }
         }
 
         let prev_col = self.columns[curr_pos - 1];
@@ -98,32 +832,153 @@ impl Environment {
         let diff_left = prev_col - curr_col;
         let diff_right = next_col - curr_col;
 
-        if prev_col > curr_col && next_col > curr_col {
+        let (handler, step) = if prev_col > curr_col && next_col > curr_col {
             // Single Width Valley - If there is backwater return it
-            return self.handle_valley(curr_pos, rain_water, diff_left, diff_right, curr_pos + 1);
+            let step =
+                self.handle_valley(curr_pos, rain_water, diff_left, diff_right, curr_pos + 1);
+            (Handler::Valley, step)
         } else if prev_col < curr_col && next_col < curr_col {
             // A Single Width Peak
-            return self.handle_peak(curr_pos, rain_water, curr_pos + 1);
+            (
+                Handler::Peak,
+                self.handle_peak(curr_pos, rain_water, curr_pos + 1),
+            )
         } else if prev_col >= curr_col && next_col < curr_col {
             // Downwards -
-            return self.handle_downwards(curr_pos, rain_water);
+            (
+                Handler::Downwards,
+                self.handle_downwards(curr_pos, rain_water),
+            )
         } else if prev_col < curr_col && next_col == curr_col {
             // Start of the S-Plateau -
-            return self.handle_s_plateau(curr_pos, rain_water);
+            (
+                Handler::SPlateau,
+                self.handle_s_plateau(curr_pos, rain_water),
+            )
         } else if prev_col > curr_col {
             // Start of a L-Plateau -
-            return self.handle_l_plateau(curr_pos, rain_water, diff_left);
+            (
+                Handler::LPlateau,
+                self.handle_l_plateau(curr_pos, rain_water, diff_left),
+            )
         } else if prev_col < curr_col && next_col > curr_col {
-            // Upwards - Return all water for now
-            return rain_water;
+            // Upwards - Return all water together with the backwater of the slope above
+            (
+                Handler::Upwards,
+                Step::Call(curr_pos + 1, T::ZERO, Then::Add(rain_water)),
+            )
         } else if prev_col == curr_col && next_col >= curr_col {
             // If on level ground just retrack to first slope
-            return rain_water;
+            (Handler::Level, Step::Return(rain_water))
         } else {
-            println!("Curr Pos: {}, rainwater: {}", curr_pos, rain_water);
-            println!("diff_left: {}, diff_right: {}", diff_left, diff_right);
-            println!("Env: {:?}", self);
-            unimplemented!("ERROR: All cases should be handled");
+            // The cases above cover every ordering of the levels, only a NaN level ends up here. The fuzz targets
+            // have not reached it from any valid relief and rain, but an overflowing `Rational` is NaN.
+            return Err(EnvError::UnhandledTopology {
+                pos: curr_pos,
+                rain_water: rain_water.to_f32(),
+                diff_left: diff_left.to_f32(),
+                diff_right: diff_right.to_f32(),
+            });
+        };
+
+        // Without any water the walk only goes on to reach the rain further right. Whatever comes back from there
+        // runs down past this column to the handlers waiting on the left, as it would have with some water.
+        let step = match step {
+            Step::Return(backwater) if rain_water == T::ZERO && backwater == T::ZERO => {
+                Step::Flow(curr_pos + 1, T::ZERO)
+            }
+            step => step,
+        };
+        Ok(self.traced(handler, curr_pos, rain_water, step))
+    }
+
+    /// Jumps from the column in position `curr_pos`, which has no water, to the next column with rain
+    ///
+    /// Walking over the drained columns in between would not change the environment, but their handlers would
+    /// wait for the backwater of the rain ahead. Of those, only the last column lower than the one before it would
+    /// take the backwater, so it runs back down to that column. The walk lands on the start of the plateau the
+    /// rain is on, whose handler gathers the rain of the whole plateau. Returns `None` if that is the current
+    /// column, which is then handled as usual.
+    fn jump_to_rain(&mut self, curr_pos: usize) -> Option<Step<T>> {
+        let next = self.next_rain_pos(curr_pos + 1);
+        if next >= self.columns.len() - 1 {
+            return Some(Step::Flow(next, T::ZERO));
+        }
+
+        let mut start = next;
+        while start > curr_pos && self.columns[start - 1] == self.columns[start] {
+            start -= 1;
+        }
+        if start == curr_pos {
+            if self.columns[curr_pos - 1] != self.columns[curr_pos] {
+                return None;
+            }
+            // The walk is already on the plateau, it goes on over level columns that take no backwater
+            start = next;
+        }
+        Some(Step::Call(start, T::ZERO, Then::RunBack(curr_pos, start)))
+    }
+
+    /// Returns the last position before `end` whose column is lower than the column before it, or 0 if there is none
+    fn last_descent(&mut self, end: usize) -> usize {
+        let mut pos = end - 1;
+        loop {
+            while self.descents[pos] != pos {
+                pos = self.descents[pos];
+            }
+            if pos == 0 || self.columns[pos - 1] > self.columns[pos] {
+                break;
+            }
+            // The column has filled up level with the one before it since the skip list was built
+            self.descents[pos] = pos - 1;
+        }
+
+        // Point every visited column straight to the result to keep the next searches short
+        let mut visited = end - 1;
+        while self.descents[visited] != pos {
+            visited = std::mem::replace(&mut self.descents[visited], pos);
+        }
+
+        pos
+    }
+
+    /// Records the `step` the `handler` has decided on in the trace, if tracing is on
+    fn traced(&mut self, handler: Handler, pos: usize, rain_water: T, step: Step<T>) -> Step<T> {
+        if self.trace.is_some() {
+            let action = match step {
+                Step::Flow(to, water) => Action::Flow {
+                    to,
+                    water: water.to_f32(),
+                },
+                Step::Call(to, water, ref then) => Action::Call {
+                    to,
+                    water: water.to_f32(),
+                    then: match *then {
+                        Then::Add(water) => Resume::Add {
+                            water: water.to_f32(),
+                        },
+                        Then::Flow(pos) => Resume::Flow { pos },
+                        Then::FlowIfAny(pos) => Resume::FlowIfAny { pos },
+                        Then::RunBack(start, end) => Resume::RunBack { start, end },
+                    },
+                },
+                Step::Return(backwater) => Action::Return {
+                    backwater: backwater.to_f32(),
+                },
+            };
+            self.record(handler, pos, rain_water, action);
+        }
+        step
+    }
+
+    fn record(&mut self, handler: Handler, pos: usize, rain_water: T, action: Action) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceStep {
+                handler,
+                pos,
+                rain_water: rain_water.to_f32(),
+                action,
+            });
         }
     }
 
@@ -131,20 +986,14 @@ impl Environment {
     ///
     /// Handles a flat peak streching from `curr_pos` to `end_pos`. Splits the rain water
     /// between left and right.
-    fn handle_peak(&mut self, _curr_pos: usize, rain_water: f32, end_pos: usize) -> f32 {
-        // println!("PEAK {} {} {}", _curr_pos, end_pos, rain_water);
-
-        let mut backwater = 0.5 * rain_water;
-        // println!("PEAK {} {} backwater {}", _curr_pos, end_pos, backwater);
-        backwater += self.flow(end_pos, 0.5 * rain_water);
-        // println!("PEAK {} {} backwater {}", _curr_pos, end_pos, backwater);
-        backwater
+    fn handle_peak(&mut self, _curr_pos: usize, rain_water: T, end_pos: usize) -> Step<T> {
+        let backwater = half(rain_water);
+        Step::Call(end_pos, half(rain_water), Then::Add(backwater))
     }
 
     /// An internal method to Handle a Valley case.
     ///
-    /// A indirect recursive function which handles a valley case in the calculation of the
-    /// water level in a vallye.
+    /// Handles a valley case in the calculation of the water level in a vallye.
     ///
     /// # Geography
     /// A column is considered to be a valley if and only if both its left and right side are strictly larger then
@@ -152,45 +1001,209 @@ impl Environment {
     ///
     /// # Water Level
     /// The water level fills up to the lowest of the 2 sides, given that there is enough rain water. If there is
-    /// rain water remaining it will spill into the lower column.
+    /// rain water remaining it will spill into the lower column. A full valley with sides of the same height spills
+    /// over both ends of its row of full pools, see `spill_row`.
     ///
     /// # Backwater
     /// Any water that cannot be returned will be backtracked, returned to the caller.
     fn handle_valley(
         &mut self,
         curr_pos: usize,
-        mut rain_water: f32,
-        left_diff: f32,
-        right_diff: f32,
+        mut rain_water: T,
+        left_diff: T,
+        right_diff: T,
         end_pos: usize,
-    ) -> f32 {
-        // println!("VALLEY {} {} {}", curr_pos, end_pos, rain_water);
-        let new_water = f32::min(
-            rain_water / (end_pos - curr_pos) as f32,
-            f32::min(left_diff, right_diff),
-        );
+    ) -> Step<T> {
+        let width: T = self.columns[curr_pos..end_pos]
+            .iter()
+            .map(Column::width)
+            .sum();
+        let rise = left_diff.min(right_diff);
+        let new_water = (rain_water / width).min(rise);
 
         for pos in curr_pos..end_pos {
             self.columns[pos].add_water(new_water);
-            rain_water -= new_water;
+            rain_water -= new_water * self.columns[pos].width;
         }
+        let rim = self.columns[curr_pos - 1]
+            .water_level()
+            .min(self.columns[end_pos].water_level());
+        if new_water < rise && self.columns[curr_pos].water_level() < rim {
+            // The valley has taken all the water, anything left is a rounding error and not backwater
+            self.leftover += rain_water;
+            rain_water = T::ZERO;
+            self.full[curr_pos..end_pos].fill(None);
+        } else {
+            // The valley is full, it stands exactly level with its rim whatever the rounding
+            for column in &mut self.columns[curr_pos..end_pos] {
+                column.level = rim;
+            }
+            if rain_water < T::EPSILON {
+                self.leftover += rain_water;
+                rain_water = T::ZERO;
+            }
 
-        if rain_water > 0. {
-            // println!("DIFFS {} {}", left_diff, right_diff);
+            let row = self.join_row(curr_pos, end_pos);
+            if right_diff == left_diff {
+                if let Some(step) = self.spill_row(row, curr_pos, rain_water, end_pos) {
+                    return step;
+                }
+            }
+        }
+
+        if rain_water > T::ZERO {
             if right_diff > left_diff {
-                return rain_water;
+                // The water runs over the left rim together with the backwater of the columns to the right
+                return Step::Call(end_pos, T::ZERO, Then::Add(rain_water));
             } else if right_diff < left_diff {
-                return self.flow(curr_pos, rain_water);
+                return Step::Flow(curr_pos, rain_water);
             }
-            return 0.5 * rain_water + self.flow(end_pos, rain_water * 0.5);
+            return Step::Call(end_pos, half(rain_water), Then::Add(half(rain_water)));
         }
 
-        rain_water = self.flow(end_pos, 0.0);
-        if rain_water > 0. {
-            return self.flow(curr_pos, rain_water);
+        Step::Call(end_pos, T::ZERO, Then::FlowIfAny(curr_pos))
+    }
+
+    /// Joins the pool from `curr_pos` to `end_pos`, which has just filled up, to the full pools at the same level
+    ///
+    /// Full pools at the same level form a row together with the rims between them, which were already at that
+    /// level. Returns the root of the row, the index of the pool in it and the start of its left rim and the end of
+    /// its right rim.
+    fn join_row(&mut self, curr_pos: usize, end_pos: usize) -> (usize, usize, (usize, usize)) {
+        let last = self.columns.len() - 1;
+        let mut rims = (curr_pos, end_pos);
+        while rims.0 > 1
+            && self.columns[rims.0 - 1] == self.columns[curr_pos]
+            && self.full[rims.0 - 1].is_none()
+        {
+            rims.0 -= 1;
+        }
+        while rims.1 < last
+            && self.columns[rims.1] == self.columns[curr_pos]
+            && self.full[rims.1].is_none()
+        {
+            rims.1 += 1;
         }
 
-        0.
+        let pool = FullPool {
+            parent: curr_pos,
+            index: 0,
+            start: rims.0,
+            end: rims.1 - 1,
+            pools: 1,
+        };
+        self.full[curr_pos..end_pos].fill(Some(pool));
+
+        // A full pool beyond a rim at the same level belongs to a row already
+        let mut root = curr_pos;
+        let mut index = 0;
+        if rims.0 < curr_pos && self.is_full_at(rims.0 - 1, curr_pos) {
+            let (left, _) = self.find_row(rims.0 - 1);
+            if let Some(row) = &mut self.full[left] {
+                index = row.pools;
+                row.end = pool.end;
+                row.pools += 1;
+            }
+            self.full[curr_pos] = Some(FullPool {
+                parent: left,
+                index,
+                ..pool
+            });
+            root = left;
+        }
+        if rims.1 > end_pos && self.is_full_at(rims.1, curr_pos) {
+            let (right, _) = self.find_row(rims.1);
+            if let (Some(row), Some(right_row)) = (self.full[root], self.full[right]) {
+                self.full[right] = Some(FullPool {
+                    parent: root,
+                    index: row.pools,
+                    ..right_row
+                });
+                self.full[root] = Some(FullPool {
+                    end: right_row.end,
+                    pools: row.pools + right_row.pools,
+                    ..row
+                });
+            }
+        }
+
+        (root, index, rims)
+    }
+
+    /// Whether the column in position `pos` belongs to a full pool at the level of the column in position `level_pos`
+    fn is_full_at(&self, pos: usize, level_pos: usize) -> bool {
+        self.full[pos].is_some() && self.columns[pos] == self.columns[level_pos]
+    }
+
+    /// Returns the root of the row of full pools the column in position `pos` belongs to and the index of its pool
+    ///
+    /// The column has to belong to a row.
+    fn find_row(&mut self, pos: usize) -> (usize, usize) {
+        let mut root = pos;
+        let mut index = 0;
+        while let Some(pool) = self.full[root].filter(|pool| pool.parent != root) {
+            index += pool.index;
+            root = pool.parent;
+        }
+
+        // Point every visited column straight to the root to keep the next searches short
+        let mut visited = pos;
+        let mut rest = index;
+        while let Some(pool) = self.full[visited]
+            .as_mut()
+            .filter(|pool| pool.parent != root)
+        {
+            let next = std::mem::replace(&mut pool.parent, root);
+            let step = std::mem::replace(&mut pool.index, rest);
+            rest -= step;
+            visited = next;
+        }
+
+        (root, index)
+    }
+
+    /// Spills the water of a full pool with rims of the same height over both ends of its row
+    ///
+    /// A full pool passes the water that reaches it evenly over both of its rims, and water that runs over a rim
+    /// runs on down its far side, even into another full pool. So of the water entering the pool with `index` in a
+    /// row of `pools` full pools, the share `(pools - index) / (pools + 1)` leaves over the left end of the row and
+    /// the rest over the right end, whatever the order in which the pools have filled. The rain of the rims is
+    /// spilled with it. Returns `None` if the row is not lower on both ends.
+    ///
+    /// The rain is placed all at once, but the share is where the water goes over time as well. The reference
+    /// simulator follows it pool by pool as the pools fill up and is compared exactly against the result.
+    fn spill_row(
+        &mut self,
+        row: (usize, usize, (usize, usize)),
+        curr_pos: usize,
+        rain_water: T,
+        end_pos: usize,
+    ) -> Option<Step<T>> {
+        let (root, index, rims) = row;
+        let FullPool {
+            start, end, pools, ..
+        } = self.full[root]?;
+        // Backwater spills over the left side once it is not higher than the first column, but water only runs
+        // over the right side from a pool next to it, a rim as high as the side is level ground
+        let level = self.columns[curr_pos];
+        let last = self.columns.len() - 1;
+        let right_open = self.columns[end + 1] < level
+            || end + 1 == last && self.full[end].is_some() && self.columns[last] <= level;
+        if self.columns[start - 1] > level || !right_open {
+            return None;
+        }
+
+        let left_rain: T = (rims.0..curr_pos).map(|pos| self.new_rain(pos)).sum();
+        let right_rain: T = (end_pos..rims.1).map(|pos| self.new_rain(pos)).sum();
+        let water = rain_water + left_rain + right_rain;
+        if water <= T::ZERO {
+            return None;
+        }
+
+        // Rain on a rim runs into the pools, or over the ends of the row, on both of its sides
+        let share = T::from_u32((pools - index) as u32);
+        let left = (water * share + half(left_rain - right_rain)) / T::from_u32(pools as u32 + 1);
+        Some(Step::Call(end + 1, water - left, Then::Add(left)))
     }
 
     /// An internal method to handle a full plateau.
@@ -198,21 +1211,19 @@ impl Environment {
     /// Handles a plateu starting with a decrease in height followed by at least 1 unit of equal height.
     ///
     /// The plateu can be either followed by an increase or further decrease.
-    fn handle_l_plateau(&mut self, curr_pos: usize, mut rain_water: f32, left_diff: f32) -> f32 {
-        // println!("L PLATEAU {} {} ", curr_pos, rain_water);
+    fn handle_l_plateau(&mut self, curr_pos: usize, mut rain_water: T, left_diff: T) -> Step<T> {
         let mut end_pos = curr_pos + 1;
-        while self.columns[curr_pos] == self.columns[end_pos] {
+        while end_pos < self.columns.len() - 1 && self.columns[curr_pos] == self.columns[end_pos] {
             rain_water += self.new_rain(end_pos);
             end_pos += 1;
         }
 
         let right_diff = self.columns[end_pos] - self.columns[curr_pos];
 
-        if right_diff > 0. {
-            return self.handle_valley(curr_pos, rain_water, left_diff, right_diff, end_pos);
+        if right_diff > T::ZERO {
+            self.handle_valley(curr_pos, rain_water, left_diff, right_diff, end_pos)
         } else {
-            let backwater = self.flow(end_pos, rain_water);
-            return self.flow(curr_pos, backwater);
+            Step::Call(end_pos, rain_water, Then::Flow(curr_pos))
         }
     }
 
@@ -224,57 +1235,83 @@ impl Environment {
     ///  (?)
     ///   |
     ///  --
-    fn handle_s_plateau(&mut self, curr_pos: usize, mut rain_water: f32) -> f32 {
-        // println!("S PLATEAU {} {} ", curr_pos, rain_water);
+    fn handle_s_plateau(&mut self, curr_pos: usize, mut rain_water: T) -> Step<T> {
         let mut end_pos = curr_pos + 1;
-        while self.columns[curr_pos] == self.columns[end_pos] {
+        while end_pos < self.columns.len() - 1 && self.columns[curr_pos] == self.columns[end_pos] {
             rain_water += self.new_rain(end_pos);
             end_pos += 1;
         }
 
         let right_diff = self.columns[end_pos] - self.columns[curr_pos];
 
-        if right_diff < 0. {
+        if right_diff < T::ZERO {
             // A (end_pos - curr_pos) wide peak
             return self.handle_peak(curr_pos, rain_water, end_pos);
         }
-        rain_water
+        // A step of a slope, its water runs down together with the backwater of the slope above
+        Step::Call(end_pos, T::ZERO, Then::Add(rain_water))
     }
 
     /// An internal method to Handle Downwards Case.
     ///
     /// Downwards case is when left water level is equal or more to the one at the current
     /// position and right water level is strictly less.
-    fn handle_downwards(&mut self, curr_pos: usize, rain_water: f32) -> f32 {
-        let backwater = self.flow(curr_pos + 1, rain_water);
-        return self.flow(curr_pos, backwater);
+    fn handle_downwards(&mut self, curr_pos: usize, rain_water: T) -> Step<T> {
+        Step::Call(curr_pos + 1, rain_water, Then::Flow(curr_pos))
     }
 }
 
-impl fmt::Display for Environment {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut max = 0;
-        for col in 1..self.columns.len() - 1 {
-            let col = self.columns[col];
-            if max < col.water_level() as u32 {
-                max = col.water_level() as u32;
-            }
-        }
+/// Returns half of the `water`, the share of each side when the water splits
+fn half<T: Number>(water: T) -> T {
+    water / T::from_u32(2)
+}
+
+/// The next piece of work of the flow algorithm, returned by the handle methods of the `Environment`
+enum Step<T> {
+    /// Flow the water into the column at the position
+    Flow(usize, T),
+    /// Flow the water into the column at the position and continue with `Then` once its backwater returns
+    Call(usize, T, Then<T>),
+    /// Return the backwater to the caller
+    Return(T),
+}
 
+/// A column of a pool that has filled up to a rim during the current rain
+///
+/// Every column points towards the root of the row of full pools it belongs to, the root keeps the extent of
+/// the row.
+#[derive(Copy, Clone, Debug)]
+struct FullPool {
+    /// Position of the next column towards the root, or of the column itself at the root
+    parent: usize,
+    /// Index of the pool in the row, counted from the index of the pool of `parent`
+    index: usize,
+    /// Position of the first column of the row, its rims included, kept up to date at the root
+    start: usize,
+    /// Position of the last column of the row, its rims included, kept up to date at the root
+    end: usize,
+    /// Number of pools in the row, kept up to date at the root
+    pools: usize,
+}
+
+/// What to do with the backwater returned by a `Step::Call`
+enum Then<T> {
+    /// Add the water to the backwater and return it
+    Add(T),
+    /// Flow the backwater into the column at the position
+    Flow(usize),
+    /// Flow the backwater into the column at the position if there is any, otherwise return no backwater
+    FlowIfAny(usize),
+    /// Flow the backwater into the last column from the first position up to the second one that is lower than the
+    /// column before it and run back again with the backwater from there, if there is any backwater and such a
+    /// column, otherwise return it
+    RunBack(usize, usize),
+}
+
+impl<T: Number> fmt::Display for Environment<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("\nSimple Result:\n")?;
-        for level in (0..max).rev() {
-            for col in 1..self.columns.len() - 1 {
-                let col = self.columns[col];
-                if col.height as u32 > level {
-                    f.write_str("O")?;
-                } else if col.water_level() as u32 > level {
-                    f.write_str("x")?;
-                } else {
-                    f.write_str(" ")?;
-                }
-            }
-            f.write_str("\n")?;
-        }
+        f.write_str(&render::render_ascii(self))?;
 
         f.write_str("\n Exact Results: \n")?;
         for col in 1..self.columns.len() - 1 {
@@ -291,42 +1328,145 @@ impl fmt::Display for Environment {
     }
 }
 
+/// Serializes the boundaries, every column and the totals of the mass balance
+impl<T: Number + Serialize> Serialize for Environment<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let balance = self.mass_balance();
+        let mut state = serializer.serialize_struct("Environment", 4)?;
+        state.serialize_field("left", &self.boundaries.0)?;
+        state.serialize_field("right", &self.boundaries.1)?;
+        state.serialize_field("columns", self.columns())?;
+        state.serialize_field(
+            "totals",
+            &Totals {
+                rain_in: balance.rain_in,
+                stored: balance.total_stored(),
+                leftover: balance.leftover,
+                evaporated: balance.evaporated,
+                infiltrated: balance.infiltrated,
+                outflow_left: balance.outflow_left,
+                outflow_right: balance.outflow_right,
+                residual: balance.residual(),
+            },
+        )?;
+        state.end()
+    }
+}
+
+/// The totals of the `MassBalance` as they are serialized with the `Environment`
+#[derive(serde::Serialize)]
+struct Totals<T> {
+    rain_in: T,
+    stored: T,
+    leftover: T,
+    evaporated: T,
+    infiltrated: T,
+    outflow_left: T,
+    outflow_right: T,
+    residual: T,
+}
+
+/// A single column of the relief with the water standing on it
 #[derive(Copy, Clone, Debug)]
-pub struct Column {
-    pub height: f32,
-    water: f32,
+pub struct Column<T = f32> {
+    height: T,
+    water: T,
+    /// Height of the water surface. It is kept next to the depth, which can not be rounded to make water standing
+    /// on columns of different heights exactly level.
+    level: T,
+    width: T,
 }
 
-impl Column {
-    pub fn new(height: f32) -> Self {
-        Self { height, water: 0. }
+impl<T: Number> Column<T> {
+    /// Constructs a new dry `Column` of width 1
+    pub fn new(height: T) -> Self {
+        Self {
+            height,
+            water: T::ZERO,
+            level: height,
+            width: T::ONE,
+        }
+    }
+
+    /// Returns the height of the column itself
+    pub fn height(&self) -> T {
+        self.height
+    }
+
+    /// Returns the depth of the water standing on the column
+    pub fn water(&self) -> T {
+        self.water
     }
 
-    pub fn water_level(&self) -> f32 {
-        self.height + self.water
+    /// Returns the width of the column
+    pub fn width(&self) -> T {
+        self.width
     }
 
-    pub fn add_water(&mut self, water: f32) {
+    /// Returns the units of water standing on the column, its depth times its width
+    pub fn volume(&self) -> T {
+        self.water * self.width
+    }
+
+    /// Returns the height of the water surface, or of the column itself if it is dry
+    pub fn water_level(&self) -> T {
+        self.level
+    }
+
+    /// Raises the water on the column by `water`
+    pub fn add_water(&mut self, water: T) {
         self.water += water;
+        self.level += water;
+    }
+
+    /// Raises the water surface of the column exactly to `level`, which must not be below it
+    pub(crate) fn fill_to(&mut self, level: T) {
+        self.water += level - self.level;
+        self.level = level;
+    }
+
+    /// Lowers the water on the column by up to `water` and returns how much it was lowered
+    pub fn remove_water(&mut self, water: T) -> T {
+        let removed = water.min(self.water);
+        self.water -= removed;
+        self.level = if self.water > T::ZERO {
+            self.level - removed
+        } else {
+            self.height
+        };
+        removed
     }
 }
 
-impl PartialEq for Column {
+/// Serializes the height, the width and the water of the column
+impl<T: Number + Serialize> Serialize for Column<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Column", 5)?;
+        state.serialize_field("height", &self.height)?;
+        state.serialize_field("width", &self.width)?;
+        state.serialize_field("water_depth", &self.water)?;
+        state.serialize_field("water_level", &self.water_level())?;
+        state.serialize_field("water_volume", &self.volume())?;
+        state.end()
+    }
+}
+
+impl<T: Number> PartialEq for Column<T> {
     fn eq(&self, other: &Self) -> bool {
         self.water_level() == other.water_level()
     }
 }
 
-impl PartialOrd for Column {
+impl<T: Number> PartialOrd for Column<T> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.water_level().partial_cmp(&other.water_level())
     }
 }
 
-impl Sub for Column {
-    type Output = f32;
+impl<T: Number> Sub for Column<T> {
+    type Output = T;
 
-    fn sub(self, rhs: Self) -> f32 {
+    fn sub(self, rhs: Self) -> T {
         self.water_level() - rhs.water_level()
     }
 }
@@ -334,23 +1474,28 @@ impl Sub for Column {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::losses::Soil;
+    use crate::number::Rational;
+    use crate::schedule::RainSchedule;
+    use crate::simulation::Simulation;
     use assert_approx_eq::assert_approx_eq as approx_eq;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_handle_valley_overflow_left() {
-        let mut env = Environment::new(vec![3, 1]);
+        let mut env = Environment::new(vec![3, 1]).unwrap();
         env.rain = vec![0., 2.];
 
-        let backwater = env.flow(2, 2.0);
+        let backwater = env.flow(2, 2.0).unwrap();
         approx_eq!(backwater, 2.);
     }
 
     #[test]
     fn test_handle_valley_overflow_right() {
-        let mut env = Environment::new(vec![3, 1, 2]);
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
         env.rain = vec![0., 0., 0.];
 
-        let backwater = env.flow(2, 2.0);
+        let backwater = env.flow(2, 2.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(2), 2.5);
@@ -359,10 +1504,10 @@ mod tests {
 
     #[test]
     fn test_handle_valley_overflow_equal() {
-        let mut env = Environment::new(vec![3, 1, 1, 3, 1]);
+        let mut env = Environment::new(vec![3, 1, 1, 3, 1]).unwrap();
         env.rain = vec![0., 0., 0., 0., 0.];
 
-        let backwater = env.flow(2, 5.0);
+        let backwater = env.flow(2, 5.0).unwrap();
         approx_eq!(backwater, 0.5);
 
         approx_eq!(env.water_level(2), 3.);
@@ -373,22 +1518,96 @@ mod tests {
 
     #[test]
     fn test_handle_valley_complete_overflow() {
-        let mut env = Environment::new(vec![3, 1, 2]);
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
         env.rain = vec![0., 0., 0.];
 
-        let backwater = env.flow(2, 4.0);
+        let backwater = env.flow(2, 4.0).unwrap();
         approx_eq!(backwater, 1.);
 
         approx_eq!(env.water_level(2), 3.);
         approx_eq!(env.water_level(3), 3.);
     }
 
+    #[test]
+    fn test_handle_valley_rounding() {
+        // A third of the rain on the plateau does not add up to the rain in f32, the rest is not backwater
+        let mut env = Environment::new(vec![5, 0, 2, 3, 2, 1, 1, 0, 3, 4]).unwrap();
+        env.rain(0.5).unwrap();
+
+        approx_eq!(env.water_level(2), 1.75);
+        approx_eq!(env.water_level(3), 2.);
+        approx_eq!(env.water_level(6), 1.75);
+        approx_eq!(env.water_level(8), 1.75);
+    }
+
+    #[test]
+    fn test_handle_valley_rounding_leftover() {
+        // Six sixths of the rain on the valley do not add up to the rain in f32, the difference is left over
+        let mut env = Environment::new(vec![9, 0, 0, 0, 0, 0, 0, 9]).unwrap();
+        let leftover = env.rain(1. / 70.).unwrap();
+
+        assert_ne!(leftover, 0.);
+        assert!(leftover.abs() < 1e-6);
+        assert_eq!(env.mass_balance().leftover, leftover);
+    }
+
+    #[test]
+    fn test_handle_valley_absorbed_rise() {
+        // The step up to the last column is lost when it is added to the water of the deeper columns
+        let mut env: Environment = Environment::from_heights(vec![
+            -1.03187733e-25,
+            -6.041662e-26,
+            -6.041662e-26,
+            1.8388e-40,
+        ])
+        .unwrap();
+        env.rain(18.625284).unwrap();
+
+        for pos in 1..=4 {
+            approx_eq!(env.water_level(pos), 18.625284);
+        }
+    }
+
+    #[test]
+    fn test_handle_valley_level_and_depth() {
+        // The depths of columns this different in height cannot add up to the same level, the pool is made
+        // level with its rim while the depths keep the water that was placed
+        let mut env: Environment = Environment::from_heights(vec![
+            9.6092035e-6,
+            1.0920506e-5,
+            1.0920513e-5,
+            3.8641897e-8,
+            -1.0920506e-5,
+            -2.1900849e-5,
+            365133.72,
+        ])
+        .unwrap();
+        for hours in [1.0920506e-5, 1.8979988e-5, 1.0920506e-5] {
+            env.rain(hours).unwrap();
+        }
+
+        let pool = &env.columns()[..6];
+        assert!(pool
+            .iter()
+            .any(|column| column.water_level() != column.height() + column.water()));
+        for column in pool {
+            assert_eq!(column.water_level(), pool[0].water_level());
+            approx_eq!(
+                column.water_level(),
+                column.height() + column.water(),
+                1e-10
+            );
+        }
+        let balance = env.mass_balance();
+        assert!(balance.residual().abs() <= 1e-6 * balance.rain_in);
+    }
+
     #[test]
     fn test_peak_splitting() {
-        let mut env = Environment::new(vec![1, 4, 2]);
+        let mut env = Environment::new(vec![1, 4, 2]).unwrap();
         env.rain = vec![0., 0., 0.];
 
-        let backwater = env.flow(2, 1.0);
+        let backwater = env.flow(2, 1.0).unwrap();
         approx_eq!(backwater, 0.5);
 
         approx_eq!(env.water_level(2), 4.);
@@ -397,10 +1616,10 @@ mod tests {
 
     #[test]
     fn test_wide_peak() {
-        let mut env = Environment::new(vec![1, 4, 4, 2]);
+        let mut env = Environment::new(vec![1, 4, 4, 2]).unwrap();
         env.rain = vec![0., 0., 1., 0.];
 
-        let backwater = env.flow(2, 1.0);
+        let backwater = env.flow(2, 1.0).unwrap();
         approx_eq!(backwater, 1.);
 
         approx_eq!(env.water_level(2), 4.);
@@ -410,10 +1629,10 @@ mod tests {
 
     #[test]
     fn test_s_steps_backwater() {
-        let mut env = Environment::new(vec![1, 4, 4, 6]);
+        let mut env = Environment::new(vec![1, 4, 4, 6]).unwrap();
         env.rain = vec![0., 0., 1., 0.];
 
-        let backwater = env.flow(2, 1.0);
+        let backwater = env.flow(2, 1.0).unwrap();
         approx_eq!(backwater, 2.);
 
         approx_eq!(env.water_level(2), 4.);
@@ -423,10 +1642,10 @@ mod tests {
 
     #[test]
     fn test_peak_overflow() {
-        let mut env = Environment::new(vec![1, 4, 2]);
+        let mut env = Environment::new(vec![1, 4, 2]).unwrap();
         env.rain = vec![0., 0., 0.];
 
-        let backwater = env.flow(2, 5.0);
+        let backwater = env.flow(2, 5.0).unwrap();
         approx_eq!(backwater, 3.);
 
         approx_eq!(env.water_level(2), 4.);
@@ -435,10 +1654,10 @@ mod tests {
 
     #[test]
     fn test_handle_plateau_valley_no_overflow() {
-        let mut env = Environment::new(vec![4, 2, 2]);
+        let mut env = Environment::new(vec![4, 2, 2]).unwrap();
         env.rain = vec![0., 0., 0.];
 
-        let backwater = env.flow(2, 3.0);
+        let backwater = env.flow(2, 3.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(2), 3.5);
@@ -447,10 +1666,10 @@ mod tests {
 
     #[test]
     fn test_handle_plateau_valley_with_overflow() {
-        let mut env = Environment::new(vec![4, 2, 2]);
+        let mut env = Environment::new(vec![4, 2, 2]).unwrap();
         env.rain = vec![0., 0., 0.];
 
-        let backwater = env.flow(2, 5.0);
+        let backwater = env.flow(2, 5.0).unwrap();
         approx_eq!(backwater, 1.);
 
         approx_eq!(env.water_level(2), 4.0);
@@ -459,10 +1678,10 @@ mod tests {
 
     #[test]
     fn test_handle_plateau_downward_no_backwater() {
-        let mut env = Environment::new(vec![4, 2, 2, 1]);
+        let mut env = Environment::new(vec![4, 2, 2, 1]).unwrap();
         env.rain = vec![0., 0., 0., 0.];
 
-        let backwater = env.flow(2, 1.0);
+        let backwater = env.flow(2, 1.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(2), 2.0);
@@ -472,10 +1691,10 @@ mod tests {
 
     #[test]
     fn test_handle_plateau_downward_with_backwater() {
-        let mut env = Environment::new(vec![4, 2, 2, 1]);
+        let mut env = Environment::new(vec![4, 2, 2, 1]).unwrap();
         env.rain = vec![0., 0., 0., 0.];
 
-        let backwater = env.flow(2, 2.0);
+        let backwater = env.flow(2, 2.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(2), 2.333333);
@@ -485,10 +1704,10 @@ mod tests {
 
     #[test]
     fn test_handle_plateau_downward_with_overflow() {
-        let mut env = Environment::new(vec![4, 2, 2, 1]);
+        let mut env = Environment::new(vec![4, 2, 2, 1]).unwrap();
         env.rain = vec![0., 0., 0., 0.];
 
-        let backwater = env.flow(2, 8.0);
+        let backwater = env.flow(2, 8.0).unwrap();
         approx_eq!(backwater, 1.);
 
         approx_eq!(env.water_level(2), 4.0);
@@ -498,10 +1717,10 @@ mod tests {
 
     #[test]
     fn test_handle_downwards_with_overflow() {
-        let mut env = Environment::new(vec![4, 3, 2, 1]);
+        let mut env = Environment::new(vec![4, 3, 2, 1]).unwrap();
         env.rain = vec![0., 0., 0., 0.];
 
-        let backwater = env.flow(2, 7.0);
+        let backwater = env.flow(2, 7.0).unwrap();
         approx_eq!(backwater, 1.);
 
         approx_eq!(env.water_level(2), 4.0);
@@ -511,16 +1730,16 @@ mod tests {
 
     #[test]
     fn test_complex_relief_no_rain() {
-        let mut env = Environment::new(vec![4, 2, 7, 8, 8, 7, 2, 4, 5, 1]);
-        let backwater = env.rain(0.0);
+        let mut env = Environment::new(vec![4, 2, 7, 8, 8, 7, 2, 4, 5, 1]).unwrap();
+        let backwater = env.rain(0.0).unwrap();
         approx_eq!(backwater, 0.);
     }
 
     #[test]
     fn test_1_cols_1_water() {
-        let mut env = Environment::new(vec![1]);
+        let mut env = Environment::new(vec![1]).unwrap();
 
-        let backwater = env.rain(1.0);
+        let backwater = env.rain(1.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(1), 2.0)
@@ -528,16 +1747,16 @@ mod tests {
 
     #[test]
     fn test_11_cols_1_water() {
-        let mut env = Environment::new(vec![1, 1]);
-        env.rain(1.0);
+        let mut env = Environment::new(vec![1, 1]).unwrap();
+        env.rain(1.0).unwrap();
         approx_eq!(env.water_level(1), 2.0)
     }
 
     #[test]
     fn test_31_cols_1_water() {
-        let mut env = Environment::new(vec![3, 1]);
+        let mut env = Environment::new(vec![3, 1]).unwrap();
 
-        let backwater = env.rain(1.0);
+        let backwater = env.rain(1.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(1), 3.0);
@@ -546,9 +1765,9 @@ mod tests {
 
     #[test]
     fn test_31_cols_2_water() {
-        let mut env = Environment::new(vec![3, 1]);
+        let mut env = Environment::new(vec![3, 1]).unwrap();
 
-        let backwater = env.rain(2.0);
+        let backwater = env.rain(2.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(1), 4.0);
@@ -557,9 +1776,9 @@ mod tests {
 
     #[test]
     fn test_13_cols_1_water() {
-        let mut env = Environment::new(vec![1, 3]);
+        let mut env = Environment::new(vec![1, 3]).unwrap();
 
-        let backwater = env.rain(1.0);
+        let backwater = env.rain(1.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(1), 3.0);
@@ -568,9 +1787,9 @@ mod tests {
 
     #[test]
     fn test_13_cols_2_water() {
-        let mut env = Environment::new(vec![1, 3]);
+        let mut env = Environment::new(vec![1, 3]).unwrap();
 
-        let backwater = env.rain(2.0);
+        let backwater = env.rain(2.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(1), 4.0);
@@ -579,9 +1798,9 @@ mod tests {
 
     #[test]
     fn test_37453_cols_2_water() {
-        let mut env = Environment::new(vec![3, 7, 4, 5, 3]);
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
 
-        let backwater = env.rain(2.0);
+        let backwater = env.rain(2.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(1), 6.0);
@@ -593,9 +1812,9 @@ mod tests {
 
     #[test]
     fn test_3_50_50_50_50_50_1_water() {
-        let mut env = Environment::new(vec![3, 50, 50, 50, 50, 50, 3]);
+        let mut env = Environment::new(vec![3, 50, 50, 50, 50, 50, 3]).unwrap();
 
-        let backwater = env.rain(1.0);
+        let backwater = env.rain(1.0).unwrap();
         approx_eq!(backwater, 0.);
 
         approx_eq!(env.water_level(1), 6.5);
@@ -608,9 +1827,9 @@ mod tests {
 
     #[test]
     fn test_316489_1_water() {
-        let mut env = Environment::new(vec![3, 1, 6, 4, 8, 9]);
+        let mut env = Environment::new(vec![3, 1, 6, 4, 8, 9]).unwrap();
 
-        let backwater = env.rain(1.0);
+        let backwater = env.rain(1.0).unwrap();
         approx_eq!(backwater, 0.);
 
         println!("{}", env);
@@ -624,9 +1843,9 @@ mod tests {
 
     #[test]
     fn test_123456789_1_water() {
-        let mut env = Environment::new(vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let mut env = Environment::new(vec![1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
 
-        let backwater = env.rain(1.0);
+        let backwater = env.rain(1.0).unwrap();
         approx_eq!(backwater, 0.);
 
         println!("{}", env);
@@ -640,4 +1859,859 @@ mod tests {
         approx_eq!(env.water_level(8), 8.);
         approx_eq!(env.water_level(9), 9.);
     }
+
+    #[test]
+    fn test_long_descending_relief_is_stack_safe() {
+        // 700 * 701 / 2 columns of rain fill exactly the lowest 700 columns up to 701
+        let len = 700 * 701 / 2;
+        let mut env = Environment::new((1..=len as u32).rev().collect()).unwrap();
+
+        let backwater = env.rain(1.0).unwrap();
+        approx_eq!(backwater, 0.);
+
+        approx_eq!(env.water_level(1), len as f32);
+        approx_eq!(env.water_level(len - 700), 701.);
+        approx_eq!(env.water_level(len - 699), 701., 1e-2);
+        approx_eq!(env.water_level(len), 701., 1e-2);
+    }
+
+    #[test]
+    fn test_long_sawtooth_relief_is_stack_safe() {
+        let len = 300_000;
+        let mut env = Environment::new((0..len).map(|pos| 1 + pos % 2).collect()).unwrap();
+
+        let backwater = env.rain(0.5).unwrap();
+        approx_eq!(backwater, 0.);
+
+        approx_eq!(env.water_level(1), 2.);
+        approx_eq!(env.water_level(2), 2.);
+        approx_eq!(env.water_level(len as usize - 1), 2.);
+        approx_eq!(env.water_level(len as usize), 2.);
+    }
+
+    #[test]
+    fn test_long_ascending_relief_is_fast() {
+        // The rain of every column runs down into the pool at the left wall, 200000 units fill the lowest 632
+        // columns up to 400028 / 632
+        let len = 200_000;
+        let mut env = Environment::<f64>::from_relief((1..=len).collect()).unwrap();
+
+        let start = Instant::now();
+        let backwater = env.rain(1.0).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        approx_eq!(backwater, 0.);
+
+        approx_eq!(env.water_level(1), 400028. / 632.);
+        approx_eq!(env.water_level(632), 400028. / 632.);
+        approx_eq!(env.water_level(633), 633.);
+        approx_eq!(env.water_level(len as usize), len as f64);
+    }
+
+    #[test]
+    fn test_rain_returns_leftover() {
+        let mut env = Environment::new(vec![1, 1, 1]).unwrap();
+
+        let leftover = env.rain(1e-8).unwrap();
+        approx_eq!(leftover, 3e-8);
+        approx_eq!(env.water_level(2), 1.);
+    }
+
+    #[test]
+    fn test_mass_balance() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+        env.rain(2.0).unwrap();
+        env.rain(0.5).unwrap();
+
+        let balance = env.mass_balance();
+        approx_eq!(balance.rain_in, 12.5);
+        approx_eq!(balance.stored[1], 0.);
+        approx_eq!(balance.total_stored(), 12.5);
+        approx_eq!(balance.leftover, 0.);
+        assert!(balance.check(1e-4).is_ok());
+    }
+
+    #[test]
+    fn test_mass_balance_with_leftover() {
+        let mut env = Environment::new(vec![4, 2, 2, 1]).unwrap();
+        env.rain(1e-8).unwrap();
+
+        let balance = env.mass_balance();
+        approx_eq!(balance.leftover, 4e-8);
+        approx_eq!(balance.residual(), 0.);
+    }
+
+    #[test]
+    fn test_empty_relief() {
+        assert!(matches!(
+            Environment::new(vec![]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+    }
+
+    #[test]
+    fn test_too_high_relief() {
+        assert!(Environment::new(vec![1, <f32 as Number>::MAX_HEIGHT]).is_ok());
+        assert!(matches!(
+            Environment::new(vec![1, <f32 as Number>::MAX_HEIGHT + 1]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_rain() {
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
+
+        assert!(matches!(env.rain(-1.), Err(EnvError::InvalidRain(_))));
+        assert!(matches!(env.rain(f32::NAN), Err(EnvError::InvalidRain(_))));
+        assert!(matches!(
+            env.rain(f32::INFINITY),
+            Err(EnvError::InvalidRain(_))
+        ));
+        assert!(matches!(env.rain(f32::MAX), Err(EnvError::InvalidRain(_))));
+
+        // Rain from the wide columns would be too deep to hold on the narrow one
+        let mut env = Environment::new(vec![1, 0, 1])
+            .unwrap()
+            .with_widths(vec![1e30, 1e-30, 1e30])
+            .unwrap();
+        assert!(matches!(env.rain(1.), Err(EnvError::InvalidRain(_))));
+        approx_eq!(env.mass_balance().rain_in, 0.);
+    }
+
+    #[test]
+    fn test_unhandled_topology() {
+        let mut env = Environment::new(vec![3, 1, 1]).unwrap();
+        env.columns[2].add_water(f32::NAN);
+        env.rain = vec![0., 1., 0.];
+
+        match env.flow(1, 0.) {
+            Err(EnvError::UnhandledTopology { pos, .. }) => assert_eq!(pos, 2),
+            other => panic!("expected an unhandled topology, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rational_overflow() {
+        let too_high = Environment::<Rational>::from_relief(vec![3, 1, 2])
+            .unwrap()
+            .with_boundaries(Boundary::Height(f32::MAX), Boundary::Open);
+        assert!(matches!(too_high, Err(EnvError::InvalidRelief(_))));
+
+        // The height fits, and too much rain for the sums to hold is an error rather than a panic
+        let mut env = Environment::<Rational>::from_relief(vec![3, 1, 2])
+            .unwrap()
+            .with_boundaries(Boundary::Height(4e9), Boundary::Wall)
+            .unwrap();
+        let rain_hours = Rational::new(i128::MAX / 8, 1);
+        assert_eq!(env.rain(rain_hours).unwrap(), Rational::ZERO);
+        assert_eq!(env.rain(rain_hours).unwrap(), Rational::ZERO);
+        assert!(matches!(
+            env.rain(rain_hours),
+            Err(EnvError::InvalidRain(_))
+        ));
+        assert_eq!(env.mass_balance().residual(), Rational::ZERO);
+    }
+
+    #[test]
+    fn test_queries() {
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
+        env.rain(1.0).unwrap();
+
+        assert_eq!(env.len(), 3);
+        assert!(!env.is_empty());
+        assert_eq!(env.columns().len(), 3);
+        approx_eq!(env.columns()[1].height(), 1.);
+        approx_eq!(env.water_depth(1), 0.);
+        approx_eq!(env.water_depth(2), 2.);
+        approx_eq!(env.water_depth(3), 1.);
+        approx_eq!(env.water_level(3), 3.);
+    }
+
+    #[test]
+    fn test_rain_profile() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+
+        let backwater = env.rain_profile(&[1., 4., 0., 0., 2.]).unwrap();
+        approx_eq!(backwater, 0.);
+
+        approx_eq!(env.water_level(1), 6.);
+        approx_eq!(env.water_level(2), 7.);
+        approx_eq!(env.water_level(3), 5.3333333);
+        approx_eq!(env.water_level(4), 5.3333333);
+        approx_eq!(env.water_level(5), 5.3333333);
+        approx_eq!(env.mass_balance().rain_in, 7.);
+    }
+
+    #[test]
+    fn test_uniform_rain_profile() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+        env.rain_profile(&[2.; 5]).unwrap();
+
+        approx_eq!(env.water_level(1), 6.0);
+        approx_eq!(env.water_level(2), 7.0);
+        approx_eq!(env.water_level(3), 6.3333333);
+    }
+
+    #[test]
+    fn test_invalid_rain_profile() {
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
+
+        assert!(matches!(
+            env.rain_profile(&[1., 1.]),
+            Err(EnvError::ProfileLength {
+                expected: 3,
+                found: 2
+            })
+        ));
+        assert!(matches!(
+            env.rain_profile(&[1., -1., 1.]),
+            Err(EnvError::InvalidRain(_))
+        ));
+    }
+
+    #[test]
+    fn test_rain_with() {
+        let mut env = Environment::new(vec![1, 1, 1, 1]).unwrap();
+        env.rain_with(|pos| pos as f32).unwrap();
+
+        approx_eq!(env.water_level(1), 3.5);
+        approx_eq!(env.water_level(4), 3.5);
+        approx_eq!(env.mass_balance().rain_in, 10.);
+    }
+
+    #[test]
+    fn test_rain_schedule() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+        let schedule = RainSchedule::new()
+            .then(0.5, 1.)
+            .then(0.5, 2.)
+            .then(1., 0.25);
+
+        let backwater = env.rain_schedule(&schedule).unwrap();
+        approx_eq!(backwater, 0.);
+
+        approx_eq!(env.water_level(1), 5.625);
+        approx_eq!(env.water_level(2), 7.);
+        approx_eq!(env.water_level(5), 6.0416667);
+        approx_eq!(env.mass_balance().rain_in, 8.75);
+    }
+
+    #[test]
+    fn test_rain_schedule_per_column() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+        let schedule = RainSchedule::new()
+            .then_profile(0.5, vec![2., 8., 0., 0., 4.])
+            .then(0., 10.);
+
+        env.rain_schedule(&schedule).unwrap();
+
+        approx_eq!(env.water_level(1), 6.);
+        approx_eq!(env.water_level(5), 5.3333333);
+    }
+
+    #[test]
+    fn test_rain_schedule_rejected() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+        env.rain(1.).unwrap();
+        let columns = env.columns().to_vec();
+        let balance = env.mass_balance();
+
+        // Only the last segment is invalid, the first may not rain either
+        let schedule = RainSchedule::new()
+            .then(1., 2.)
+            .then_profile(1., vec![1., 1., f32::INFINITY, 1., 1.]);
+        assert!(matches!(
+            env.rain_schedule(&schedule),
+            Err(EnvError::InvalidRain(_))
+        ));
+        assert_eq!(env.columns(), &columns[..]);
+        assert_eq!(env.mass_balance(), balance);
+    }
+
+    #[test]
+    fn test_remove_water() {
+        let mut column = Column::new(1.);
+        column.add_water(2.);
+
+        approx_eq!(column.remove_water(0.5), 0.5);
+        approx_eq!(column.water_level(), 2.5);
+        approx_eq!(column.remove_water(4.), 1.5);
+        approx_eq!(column.water_level(), 1.);
+    }
+
+    #[test]
+    fn test_evaporation() {
+        let mut env = Environment::new(vec![4, 1, 4]).unwrap();
+        env.rain(1.0).unwrap();
+        env.set_losses(Losses::new(0.5)).unwrap();
+
+        let lost = env.apply_losses(2.).unwrap();
+        approx_eq!(lost, 1.);
+        approx_eq!(env.water_level(2), 3.);
+
+        let balance = env.mass_balance();
+        approx_eq!(balance.evaporated, 1.);
+        approx_eq!(balance.infiltrated, 0.);
+        approx_eq!(balance.residual(), 0.);
+    }
+
+    #[test]
+    fn test_losses_only_in_steps() {
+        let mut dry = Environment::new(vec![5, 0, 5]).unwrap();
+        dry.set_losses(Losses::new(0.5)).unwrap();
+
+        // Raining only adds water, whether it is hours, a profile or a schedule
+        let mut env = dry.clone();
+        env.rain(1.0).unwrap();
+        env.rain_profile(&[0., 1., 0.]).unwrap();
+        approx_eq!(env.water_depth(2), 4.);
+        approx_eq!(env.mass_balance().evaporated, 0.);
+
+        // A simulation applies the losses after every step, which is the same as applying them after every segment
+        let schedule = RainSchedule::new().then(1., 1.).then(2., 0.);
+        let mut simulation = Simulation::new(dry.clone());
+        simulation.run_schedule(&schedule, 1.).unwrap();
+        approx_eq!(simulation.environment().water_depth(2), 1.5);
+
+        let mut env = dry;
+        env.rain_schedule(&schedule).unwrap();
+        approx_eq!(env.water_depth(2), 3.);
+        env.apply_losses(schedule.duration()).unwrap();
+        approx_eq!(env.water_depth(2), 1.5);
+        approx_eq!(env.mass_balance().evaporated, 1.5);
+    }
+
+    #[test]
+    fn test_infiltration_lowers_whole_pond() {
+        let mut env = Environment::new(vec![3, 1, 1, 3]).unwrap();
+        env.rain(1.0).unwrap();
+        env.set_losses(Losses::new(0.).with_soil(&[
+            Soil::Rock,
+            Soil::Sand,
+            Soil::Rock,
+            Soil::Rock,
+        ]))
+        .unwrap();
+
+        let lost = env.apply_losses(2.).unwrap();
+        approx_eq!(lost, 1.);
+        approx_eq!(env.water_level(2), 2.5);
+        approx_eq!(env.water_level(3), 2.5);
+        approx_eq!(env.mass_balance().infiltrated, 1.);
+    }
+
+    #[test]
+    fn test_losses_shrink_pond() {
+        let mut env = Environment::new(vec![4, 1, 2, 4]).unwrap();
+        env.columns[2].add_water(2.);
+        env.columns[3].add_water(1.);
+        env.set_losses(Losses::new(1.)).unwrap();
+
+        env.apply_losses(1.).unwrap();
+        approx_eq!(env.water_level(2), 2.);
+        approx_eq!(env.water_depth(3), 0.);
+    }
+
+    #[test]
+    fn test_losses_dry_pond() {
+        let mut env = Environment::new(vec![4, 1, 4]).unwrap();
+        env.rain(0.5).unwrap();
+        env.set_losses(Losses::new(1.).with_infiltration(vec![0., 1., 0.]))
+            .unwrap();
+
+        let lost = env.apply_losses(2.).unwrap();
+        approx_eq!(lost, 1.5);
+        approx_eq!(env.water_depth(2), 0.);
+
+        let balance = env.mass_balance();
+        approx_eq!(balance.evaporated, 0.75);
+        approx_eq!(balance.infiltrated, 0.75);
+        approx_eq!(balance.residual(), 0.);
+    }
+
+    #[test]
+    fn test_no_losses() {
+        let mut env = Environment::new(vec![4, 1, 4]).unwrap();
+        env.rain(1.0).unwrap();
+
+        approx_eq!(env.apply_losses(2.).unwrap(), 0.);
+        approx_eq!(env.water_level(2), 4.);
+        assert!(matches!(
+            env.set_losses(Losses::new(f32::NAN)),
+            Err(EnvError::InvalidRate(_))
+        ));
+    }
+
+    #[test]
+    fn test_spill_over_left_wall() {
+        let mut env = Environment::new(vec![1, 5])
+            .unwrap()
+            .with_boundaries(Boundary::Height(3.), Boundary::Wall)
+            .unwrap();
+
+        let backwater = env.rain(2.0).unwrap();
+        approx_eq!(backwater, 0.);
+
+        approx_eq!(env.water_level(1), 3.);
+        approx_eq!(env.water_level(2), 5.);
+        approx_eq!(env.outflow().0, 2.);
+        approx_eq!(env.outflow().1, 0.);
+    }
+
+    #[test]
+    fn test_drain_over_open_right_side() {
+        let mut env = Environment::new(vec![3, 1])
+            .unwrap()
+            .with_boundaries(Boundary::Wall, Boundary::Open)
+            .unwrap();
+        env.rain(1.0).unwrap();
+
+        approx_eq!(env.water_level(1), 3.);
+        approx_eq!(env.water_level(2), 1.);
+        approx_eq!(env.outflow().1, 2.);
+    }
+
+    #[test]
+    fn test_spill_over_both_walls() {
+        let mut env = Environment::new(vec![2, 1, 2])
+            .unwrap()
+            .with_boundaries(Boundary::Height(2.5), Boundary::Height(2.5))
+            .unwrap();
+        env.rain(1.0).unwrap();
+
+        approx_eq!(env.water_level(1), 2.5);
+        approx_eq!(env.water_level(3), 2.5);
+        approx_eq!(env.outflow().0 + env.outflow().1, 0.5);
+    }
+
+    #[test]
+    fn test_open_sides_keep_valleys() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Open)
+            .unwrap();
+        env.rain(2.0).unwrap();
+
+        approx_eq!(env.water_level(1), 3.);
+        approx_eq!(env.water_level(3), 5.);
+        approx_eq!(env.water_level(5), 3.);
+
+        let balance = env.mass_balance();
+        approx_eq!(balance.outflow_left, 3.);
+        approx_eq!(balance.outflow_right, 6.);
+        approx_eq!(balance.leftover, 0.);
+        approx_eq!(balance.residual(), 0.);
+    }
+
+    #[test]
+    fn test_row_of_full_pools() {
+        // The water spilled by the left pool partly runs through the full right pool, whatever fills up first
+        let mut env = Environment::new(vec![6, 0, 6, 0, 6])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Open)
+            .unwrap();
+        env.rain_profile(&[0., 8., 0., 6., 0.]).unwrap();
+
+        approx_eq!(env.water_level(2), 6.);
+        approx_eq!(env.water_level(4), 6.);
+        approx_eq!(env.outflow().0, 4. / 3.);
+        approx_eq!(env.outflow().1, 2. / 3.);
+    }
+
+    #[test]
+    fn test_equal_rims_changed() {
+        // A full pool used to spill the water it received from the left over its right rim and the water it
+        // received from the right over its left rim, so these results changed when rows of full pools came in.
+        // They break the promise of the iterative solver to keep the levels of the recursive one.
+        let open = |relief: Vec<u32>| {
+            Environment::new(relief)
+                .unwrap()
+                .with_boundaries(Boundary::Open, Boundary::Open)
+                .unwrap()
+        };
+
+        // Before: 1.5 units over the left side and 2.5 over the right side of a symmetric relief
+        let mut env = open(vec![3, 1, 3]);
+        env.rain(2.).unwrap();
+        approx_eq!(env.outflow().0, 2.);
+        approx_eq!(env.outflow().1, 2.);
+
+        // Before: the left pool rose to 2.75 and 1.25 units left over the right side
+        let mut env = Environment::new(vec![1, 7, 6, 7])
+            .unwrap()
+            .with_boundaries(Boundary::Wall, Boundary::Open)
+            .unwrap();
+        env.rain(1.).unwrap();
+        approx_eq!(env.water_level(1), 3.);
+        approx_eq!(env.outflow().1, 1.);
+
+        // Before: 1.3125 units over the left side after 1.875 hours but only 1 after 2 hours
+        let mut env = open(vec![5, 2, 5, 4]);
+        env.rain(1.875).unwrap();
+        approx_eq!(env.outflow().0, 1.3125);
+        approx_eq!(env.outflow().1, 3.1875);
+        let mut env = open(vec![5, 2, 5, 4]);
+        env.rain(2.).unwrap();
+        approx_eq!(env.outflow().0, 1.5);
+        approx_eq!(env.outflow().1, 3.5);
+
+        // Walled reliefs changed as well. The valleys between equal rims used to pass the rounding residue of
+        // their water on as backwater, which sent the rain of the columns right of them back to the first column,
+        // and a full pool lost part of what it spilled when the water of its right rim reached it last.
+        let walled = |relief: Vec<u32>, levels: &[f32]| {
+            let mut env = Environment::new(relief).unwrap();
+            env.rain(1.).unwrap();
+            for (pos, level) in levels.iter().enumerate() {
+                approx_eq!(env.water_level(pos + 1), *level, 1e-5);
+            }
+        };
+        // Before: 2.5, 4, 1.1667, 1.1667, 1.1667, 4
+        walled(vec![0, 4, 0, 0, 0, 4], &[1.5, 4., 1.5, 1.5, 1.5, 4.]);
+        // Before: 3.25, 3.25, 4, 2.1667, 2.1667, 2.1667, 4
+        walled(
+            vec![1, 2, 4, 1, 1, 1, 4],
+            &[2.75, 2.75, 4., 2.5, 2.5, 2.5, 4.],
+        );
+        // Before: 4, 3.75, 5, 5, 5, 2.625, 2.625
+        walled(vec![4, 1, 5, 4, 5, 2, 0], &[4., 4., 5., 5., 5., 2.5, 2.5]);
+    }
+
+    #[test]
+    fn test_invalid_boundary() {
+        assert_eq!(
+            Environment::new(vec![1]).unwrap().boundaries(),
+            (Boundary::Wall, Boundary::Wall)
+        );
+        assert!(matches!(
+            Environment::new(vec![1])
+                .unwrap()
+                .with_boundaries(Boundary::Height(f32::INFINITY), Boundary::Open),
+            Err(EnvError::InvalidRelief(_))
+        ));
+    }
+
+    #[test]
+    fn test_basins() {
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3, 6]).unwrap();
+        env.rain(1.0).unwrap();
+
+        let basins = env.basins();
+        assert_eq!(basins.len(), 2);
+
+        assert_eq!((basins[0].start, basins[0].end), (1, 1));
+        approx_eq!(basins[0].level, 4.5);
+        approx_eq!(basins[0].volume, 1.5);
+        approx_eq!(basins[0].max_depth, 1.5);
+        assert_eq!(basins[0].spill.pos, 2);
+        approx_eq!(basins[0].spill.level, 7.);
+
+        assert_eq!((basins[1].start, basins[1].end), (3, 5));
+        approx_eq!(basins[1].volume, 4.5);
+        approx_eq!(basins[1].level, 5.5);
+        approx_eq!(basins[1].max_depth, 2.5);
+        // The water spreads onto the column of 6 against the wall, so it overflows the column of 7
+        assert_eq!(basins[1].spill.pos, 2);
+        approx_eq!(basins[1].spill.level, 7.);
+    }
+
+    #[test]
+    fn test_full_basin() {
+        let mut env = Environment::new(vec![3, 1, 5])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Wall)
+            .unwrap();
+        env.rain(2.0).unwrap();
+
+        let basins = env.basins();
+        assert_eq!(basins.len(), 1);
+        assert_eq!(basins[0].spill.pos, 1);
+        assert!(basins[0].is_full());
+    }
+
+    #[test]
+    fn test_basin_bounded_by_side() {
+        let mut env = Environment::new(vec![1, 2, 3]).unwrap();
+        assert!(env.basins().is_empty());
+
+        env.rain(0.5).unwrap();
+        assert_eq!((env.basins()[0].start, env.basins()[0].end), (1, 2));
+        approx_eq!(env.basins()[0].level, 2.25);
+        // The water spreads up to the walls on both sides and never overflows
+        assert_eq!(env.basins()[0].spill.pos, 0);
+        assert_eq!(env.basins()[0].spill.level, f32::MAX);
+        assert!(!env.basins()[0].is_full());
+    }
+
+    #[test]
+    fn test_hours_to_level() {
+        let env = Environment::new(vec![4, 1, 4]).unwrap();
+        approx_eq!(env.hours_to_level(2, 3.).unwrap(), 0.6666667);
+        approx_eq!(env.hours_to_level(2, 1.).unwrap(), 0.);
+
+        // The left pit fills up alone until it spills over the peak after 1.3333333 hours
+        let env = Environment::new(vec![1, 3, 1]).unwrap();
+        approx_eq!(env.hours_to_level(1, 2.).unwrap(), 0.6666667);
+        approx_eq!(env.hours_to_level(1, 3.5).unwrap(), 1.8333333, 1e-5);
+        approx_eq!(env.hours_to_level(1, 4.).unwrap(), 2.3333333, 1e-5);
+
+        let mut rained = env.clone();
+        rained.rain(2.3333333).unwrap();
+        approx_eq!(rained.water_level(1), 4., 1e-5);
+        approx_eq!(env.water_level(1), 1.);
+    }
+
+    #[test]
+    fn test_hours_to_volume() {
+        let env = Environment::new(vec![4, 1, 4]).unwrap();
+        approx_eq!(env.hours_to_volume(2, 1.5).unwrap(), 0.5);
+
+        let env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+        approx_eq!(env.hours_to_volume(3, 0.5).unwrap(), 0.25);
+
+        // The basin over position 2 holds 1 unit after 0.4 hours, then it merges with the one over position 4
+        let env = Environment::new(vec![2, 0, 1, 0, 2]).unwrap();
+        approx_eq!(env.hours_to_volume(2, 1.5).unwrap(), 0.4);
+    }
+
+    #[test]
+    fn test_unreachable_target() {
+        let env = Environment::new(vec![2, 1, 2])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Open)
+            .unwrap();
+
+        assert!(matches!(
+            env.hours_to_level(2, 3.),
+            Err(EnvError::Unreachable(_))
+        ));
+        assert!(matches!(
+            env.hours_to_level(2, f32::NAN),
+            Err(EnvError::InvalidTarget(_))
+        ));
+    }
+
+    #[test]
+    fn test_rational_backend() {
+        let mut env =
+            Environment::<Rational>::from_relief(vec![1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+
+        let backwater = env.rain(Rational::ONE).unwrap();
+        assert_eq!(backwater, Rational::ZERO);
+        assert_eq!(env.water_level(1), Rational::new(19, 4));
+        assert_eq!(env.water_level(4), Rational::new(19, 4));
+        assert_eq!(env.water_level(5), Rational::from(5));
+        assert_eq!(env.mass_balance().residual(), Rational::ZERO);
+    }
+
+    #[test]
+    fn test_rational_backend_is_exact() {
+        let relief = vec![5, 0, 2, 3, 2, 1, 1, 0, 3, 4];
+        let mut env = Environment::<Rational>::from_relief(relief).unwrap();
+        env.rain(Rational::new(1, 2)).unwrap();
+
+        assert_eq!(env.water_level(2), Rational::new(7, 4));
+        for pos in 6..=8 {
+            assert_eq!(env.water_level(pos), Rational::new(7, 4));
+        }
+        assert_eq!(env.mass_balance().residual(), Rational::ZERO);
+    }
+
+    #[test]
+    fn test_f64_backend() {
+        let mut env = Environment::<f64>::from_relief(vec![3, 7, 4, 5, 3])
+            .unwrap()
+            .with_boundaries(Boundary::Wall, Boundary::Open)
+            .unwrap();
+        env.rain(2.0).unwrap();
+
+        assert_eq!(env.water_level(1), 6.);
+        assert_eq!(env.water_level(3), 5.);
+        assert_eq!(env.outflow(), (0., 6.));
+        assert!(env.mass_balance().check(1e-12).is_ok());
+    }
+
+    #[test]
+    fn test_real_heights() {
+        let mut env = Environment::from_heights(vec![-1.5f32, -3., 0.5]).unwrap();
+        env.rain(1.0).unwrap();
+
+        approx_eq!(env.water_level(1), -0.75);
+        approx_eq!(env.water_level(2), -0.75);
+        approx_eq!(env.water_depth(2), 2.25);
+        approx_eq!(env.water_level(3), 0.5);
+        assert!(env.to_string().contains("water_level at -0.75"));
+    }
+
+    #[test]
+    fn test_invalid_real_heights() {
+        assert!(matches!(
+            Environment::<f32>::from_heights(vec![]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Environment::from_heights(vec![1., f32::NAN]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Environment::from_heights(vec![f64::NEG_INFINITY]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+
+        // Heights beyond `MAX_HEIGHT` and walls as high are not stored closely enough to tell the water levels apart
+        let max = <f32 as Number>::MAX_HEIGHT as f32;
+        assert!(Environment::from_heights(vec![max, -max]).is_ok());
+        assert!(matches!(
+            Environment::from_heights(vec![1., -2. * max]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Environment::new(vec![1])
+                .unwrap()
+                .with_boundaries(Boundary::Wall, Boundary::Height(2. * max)),
+            Err(EnvError::InvalidRelief(_))
+        ));
+    }
+
+    #[test]
+    fn test_widths() {
+        let mut env = Environment::new(vec![3, 1, 3])
+            .unwrap()
+            .with_widths(vec![1., 2., 1.])
+            .unwrap();
+        env.rain(1.0).unwrap();
+
+        approx_eq!(env.water_level(2), 3.);
+        approx_eq!(env.water_depth(2), 2.);
+        approx_eq!(env.water_volume(2), 4.);
+        approx_eq!(env.mass_balance().rain_in, 4.);
+        approx_eq!(env.mass_balance().residual(), 0.);
+    }
+
+    #[test]
+    fn test_widths_spread_over_plateau() {
+        let mut env = Environment::new(vec![5, 1, 1, 5])
+            .unwrap()
+            .with_widths(vec![1., 1., 3., 1.])
+            .unwrap();
+        env.rain(1.0).unwrap();
+
+        approx_eq!(env.water_level(2), 2.5);
+        approx_eq!(env.water_level(3), 2.5);
+        approx_eq!(env.water_volume(2), 1.5);
+        approx_eq!(env.water_volume(3), 4.5);
+        approx_eq!(env.basins()[0].volume, 6.);
+
+        env.set_losses(Losses::new(0.25)).unwrap();
+        let lost = env.apply_losses(1.).unwrap();
+        approx_eq!(lost, 1.);
+        approx_eq!(env.water_level(3), 2.25);
+    }
+
+    #[test]
+    fn test_invalid_widths() {
+        assert!(matches!(
+            Environment::new(vec![1, 2]).unwrap().with_widths(vec![1.]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Environment::new(vec![1, 2])
+                .unwrap()
+                .with_widths(vec![1., 0.]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Environment::new(vec![1, 2])
+                .unwrap()
+                .with_widths(vec![f32::MAX, f32::MAX]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
+        env.rain(1.0).unwrap();
+
+        let json = serde_json::to_value(&env).unwrap();
+        assert_eq!(json["left"], "wall");
+        assert_eq!(json["columns"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            json["columns"][1],
+            serde_json::json!({
+                "height": 1.0,
+                "width": 1.0,
+                "water_depth": 2.0,
+                "water_level": 3.0,
+                "water_volume": 2.0
+            })
+        );
+        assert_eq!(json["totals"]["rain_in"], 3.0);
+        assert_eq!(json["totals"]["stored"], 3.0);
+        assert_eq!(json["totals"]["residual"], 0.0);
+
+        let mut env = Environment::<Rational>::from_relief(vec![2, 0, 2]).unwrap();
+        env.rain(Rational::new(4, 3)).unwrap();
+        let json = serde_json::to_value(&env).unwrap();
+        assert_eq!(json["columns"][0]["water_level"], "8/3");
+        assert_eq!(json["columns"][1]["water_depth"], "8/3");
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
+        assert!(env.trace().is_none());
+
+        env.start_trace();
+        env.rain(1.0).unwrap();
+        let trace = env.stop_trace().unwrap();
+        assert!(env.trace().is_none());
+        assert_eq!(trace.steps().len(), 6);
+
+        let valley = trace
+            .steps()
+            .iter()
+            .find(|step| step.handler == Handler::Valley)
+            .unwrap();
+        assert_eq!(valley.pos, 2);
+        assert_eq!(
+            trace.steps()[0],
+            TraceStep {
+                handler: Handler::Downwards,
+                pos: 1,
+                rain_water: 1.,
+                action: Action::Call {
+                    to: 2,
+                    water: 1.,
+                    then: Resume::Flow { pos: 1 },
+                },
+            }
+        );
+
+        let json = serde_json::from_str::<serde_json::Value>(&trace.to_json()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), trace.steps().len());
+        assert_eq!(json[0]["handler"], "downwards");
+        assert_eq!(json[0]["action"], "call");
+        assert_eq!(json[0]["then"]["resume"], "flow");
+    }
+
+    #[test]
+    fn test_trace_spill() {
+        let mut env = Environment::new(vec![1, 2])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Wall)
+            .unwrap();
+        env.start_trace();
+        env.rain(1.0).unwrap();
+
+        // The rain of the second column runs down the first one and spills over the open left side with its rain
+        let spills = env
+            .trace()
+            .unwrap()
+            .steps()
+            .iter()
+            .filter(|step| step.handler == Handler::LeftSide)
+            .map(|step| step.action)
+            .collect::<Vec<Action>>();
+        assert_eq!(spills, vec![Action::Spill { water: 2. }]);
+    }
 }
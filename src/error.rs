@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::balance::Imbalance;
+
+/// Errors returned by the `Environment` and the command line interface
+#[derive(Debug)]
+pub enum EnvError {
+    /// The relief cannot be used to construct an `Environment`
+    InvalidRelief(String),
+    /// The amount of rain is negative or not a finite number
+    InvalidRain(f32),
+    /// A loss rate is negative or not a finite number
+    InvalidRate(f32),
+    /// The time step is not a finite, positive number of hours
+    InvalidStep(f32),
+    /// The target of an inverse query is not a finite number
+    InvalidTarget(f32),
+    /// The target of an inverse query is never reached, however long it rains
+    Unreachable(f32),
+    /// An inverse query has not narrowed down the rain that reaches its target within its iterations
+    NotConverged(f32),
+    /// The rain profile does not have one value for every column of the relief
+    ProfileLength { expected: usize, found: usize },
+    /// The flow algorithm has reached a relief shape it does not handle, which only NaN water levels lead to,
+    /// like those of a `Rational` whose fractions have overflowed
+    UnhandledTopology {
+        /// Position of the column where the algorithm stopped
+        pos: usize,
+        /// Rain water that was flowing into the column
+        rain_water: f32,
+        /// Difference between the water levels of the left and the current column
+        diff_left: f32,
+        /// Difference between the water levels of the right and the current column
+        diff_right: f32,
+    },
+    /// The rain did not add up with the water stored in the relief
+    Imbalance(Imbalance),
+    /// Reading the input or writing the output has failed
+    Io(io::Error),
+    /// The command line arguments are not valid
+    Usage(String),
+    /// The JSON input could not be parsed
+    Json(serde_json::Error),
+    /// The input could not be parsed
+    Parse {
+        /// The part of the input that could not be parsed
+        input: String,
+        /// What the input was expected to be
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvError::InvalidRelief(reason) => write!(f, "invalid relief: {}", reason),
+            EnvError::InvalidRain(rain) => write!(
+                f,
+                "invalid rain: {} is not a finite, non-negative amount",
+                rain
+            ),
+            EnvError::InvalidRate(rate) => write!(
+                f,
+                "invalid loss rate: {} is not a finite, non-negative rate",
+                rate
+            ),
+            EnvError::InvalidStep(step) => write!(
+                f,
+                "invalid time step: {} is not a finite, positive number of hours",
+                step
+            ),
+            EnvError::InvalidTarget(target) => {
+                write!(f, "invalid target: {} is not a finite number", target)
+            }
+            EnvError::Unreachable(target) => write!(
+                f,
+                "the target of {} is never reached, however long it rains",
+                target
+            ),
+            EnvError::NotConverged(target) => write!(
+                f,
+                "the rain reaching the target of {} could not be narrowed down",
+                target
+            ),
+            EnvError::ProfileLength { expected, found } => write!(
+                f,
+                "invalid rain profile: expected {} values, one for each column, found {}",
+                expected, found
+            ),
+            EnvError::UnhandledTopology {
+                pos,
+                rain_water,
+                diff_left,
+                diff_right,
+            } => write!(
+                f,
+                "unhandled relief at column {} with {} units of rain water (diff_left: {}, diff_right: {})",
+                pos, rain_water, diff_left, diff_right
+            ),
+            EnvError::Imbalance(imbalance) => imbalance.fmt(f),
+            EnvError::Io(err) => write!(f, "I/O error: {}", err),
+            EnvError::Usage(reason) => write!(f, "invalid arguments: {}", reason),
+            EnvError::Json(err) => write!(f, "could not parse the JSON input: {}", err),
+            EnvError::Parse { input, expected } => {
+                write!(f, "could not parse `{}` as {}", input, expected)
+            }
+        }
+    }
+}
+
+impl Error for EnvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EnvError::Imbalance(imbalance) => Some(imbalance),
+            EnvError::Io(err) => Some(err),
+            EnvError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EnvError {
+    fn from(err: io::Error) -> Self {
+        EnvError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for EnvError {
+    fn from(err: serde_json::Error) -> Self {
+        // Failing to write the JSON is not a problem with the JSON itself
+        if err.is_io() {
+            EnvError::Io(err.into())
+        } else {
+            EnvError::Json(err)
+        }
+    }
+}
+
+impl From<Imbalance> for EnvError {
+    fn from(imbalance: Imbalance) -> Self {
+        EnvError::Imbalance(imbalance)
+    }
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+
+use crate::basin::Basin;
+use crate::env::{Column, Environment};
+use crate::error::EnvError;
+use crate::number::Number;
+
+/// Precision of the onsets of the events, relative to the hours of the rain
+const EVENT_TOLERANCE: f32 = 1e-5;
+
+/// What has happened in a flood `Event`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    /// Water has started standing in a basin which was dry
+    BasinStarted,
+    /// A peak has been covered by water
+    PeakSubmerged,
+    /// Two or more basins have merged into one lake
+    BasinsMerged,
+    /// A basin has filled up to its spill level and starts overflowing
+    BasinFull,
+}
+
+/// A transition of the flood observed while it rains
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Event {
+    /// Hours of rain when the event happened
+    pub time: f32,
+    pub kind: EventKind,
+    /// Position of the first column involved
+    pub start: usize,
+    /// Position of the last column involved
+    pub end: usize,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            EventKind::BasinStarted => "water standing on",
+            EventKind::PeakSubmerged => "peak submerged at",
+            EventKind::BasinsMerged => "basins merged over",
+            EventKind::BasinFull => "basin full over",
+        };
+        if self.start == self.end {
+            write!(f, "{} column {}", what, self.start)
+        } else {
+            write!(f, "{} columns {} to {}", what, self.start, self.end)
+        }
+    }
+}
+
+/// A run of columns higher than the columns or the sides on both of its ends
+#[derive(Copy, Clone, Debug)]
+struct Peak {
+    start: usize,
+    end: usize,
+    submerged: bool,
+}
+
+/// Watches the `Environment` while it rains and records the `Events`
+///
+/// The events are found by comparing the basins before and after every rain. If they differ, the rain is repeated
+/// for fewer hours on an untraced copy of the state before it. As long as the basins cover the same columns, their
+/// levels rise linearly with the rain, so the hours when the first of them reaches the top of a neighbouring column
+/// are interpolated and checked by one rain just before and one just after them. Where the check fails, the
+/// interval is halved instead, until the onset is known within `EVENT_TOLERANCE` of the hours. The events of that
+/// change are recorded, and the search goes on from there, so every transition gets its own onset even if several
+/// of them happen during one rain.
+#[derive(Clone, Debug)]
+pub(crate) struct EventLog<T = f32> {
+    /// Heights of all the columns, including the sides
+    heights: Vec<T>,
+    basins: Vec<Basin<T>>,
+    peaks: Vec<Peak>,
+    events: Vec<Event>,
+}
+
+impl<T: Number> EventLog<T> {
+    /// Constructs a new `EventLog` which takes the current state of `env` as the starting point
+    pub(crate) fn new(env: &Environment<T>) -> Self {
+        let (left, right) = env.boundaries();
+        let mut heights = vec![left.height::<T>()];
+        heights.extend(env.columns().iter().map(Column::height));
+        heights.push(right.height());
+
+        let mut peaks = vec![];
+        let mut pos = 1;
+        while pos < heights.len() - 1 {
+            let start = pos;
+            while pos < heights.len() - 1 && heights[pos] == heights[start] {
+                pos += 1;
+            }
+            if heights[start - 1] < heights[start] && heights[pos] < heights[start] {
+                peaks.push(Peak {
+                    start,
+                    end: pos - 1,
+                    submerged: (start..pos).any(|pos| env.water_depth(pos) > T::ZERO),
+                });
+            }
+        }
+
+        Self {
+            heights,
+            basins: env.basins(),
+            peaks,
+            events: vec![],
+        }
+    }
+
+    /// Returns all the events recorded so far, in order
+    pub(crate) fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Consumes the log and returns all the events recorded
+    pub(crate) fn into_events(self) -> Vec<Event> {
+        self.events
+    }
+
+    /// Records the events of a rain of `hours` hours, which started at `time` hours and turned `before` into `after`
+    ///
+    /// `rain` lets it rain for the given hours on a copy of `before`, the same way as it has rained on `after`.
+    pub(crate) fn record<F>(
+        &mut self,
+        before: &Environment<T>,
+        after: &Environment<T>,
+        time: f32,
+        hours: f32,
+        rain: F,
+    ) -> Result<(), EnvError>
+    where
+        F: Fn(&mut Environment<T>, f32) -> Result<(), EnvError>,
+    {
+        let target = after.basins();
+        let tolerance = EVENT_TOLERANCE * hours;
+        // Every rain starts over from the state before, in the memory of the same copy
+        let mut scratch = before.untraced();
+        let mut basins_after = |hours: f32| -> Result<Vec<Basin<T>>, EnvError> {
+            scratch.clone_untraced_from(before);
+            rain(&mut scratch, hours)?;
+            Ok(scratch.basins())
+        };
+        let mut from = 0.;
+
+        while changed(&self.basins, &target) {
+            let (mut low, mut low_basins) = (from, self.basins.clone());
+            let (mut high, mut basins) = (hours, target.clone());
+            while high - low > tolerance {
+                // Rain just after and just before the expected onset, then halve what is left of the interval
+                let expected = self.expected_onset(from, low, &low_basins, tolerance);
+                let around = expected
+                    .into_iter()
+                    .flat_map(|onset| [onset + tolerance / 4., onset - tolerance / 4.]);
+                for guess in around.map(Some).chain(Some(None)) {
+                    let middle = guess.unwrap_or((low + high) / 2.);
+                    if middle <= low || middle >= high {
+                        continue;
+                    }
+                    let middle_basins = basins_after(middle)?;
+                    if changed(&self.basins, &middle_basins) {
+                        (high, basins) = (middle, middle_basins);
+                    } else {
+                        (low, low_basins) = (middle, middle_basins);
+                    }
+                    if high - low <= tolerance {
+                        break;
+                    }
+                }
+            }
+            let onset = self
+                .expected_onset(from, low, &low_basins, tolerance)
+                .filter(|onset| low <= *onset && *onset <= high)
+                .unwrap_or(low);
+            self.compare(basins, time + onset);
+            from = high;
+        }
+        self.basins = target;
+        Ok(())
+    }
+
+    /// Returns the hours when the first basin reaches the top of a neighbouring column, if the basins keep rising
+    /// from `low_basins` after `low` hours as they have since the last basins after `from` hours
+    ///
+    /// Right at `from`, water may start standing or spilling at once, so that is the first guess. The levels are not
+    /// interpolated until they have risen for more than the `tolerance`, as `f32` does not resolve them any closer.
+    fn expected_onset(
+        &self,
+        from: f32,
+        low: f32,
+        low_basins: &[Basin<T>],
+        tolerance: f32,
+    ) -> Option<f32> {
+        if low <= from {
+            return Some(from);
+        }
+        if low - from <= tolerance {
+            return None;
+        }
+        self.basins
+            .iter()
+            .zip(low_basins)
+            .filter_map(|(start, basin)| {
+                let rim = self.heights[basin.start - 1].min(self.heights[basin.end + 1]);
+                let rise = (basin.level - start.level).to_f32();
+                (rim > basin.level && rise > 0.)
+                    .then(|| low + (rim - basin.level).to_f32() * (low - from) / rise)
+            })
+            .reduce(f32::min)
+    }
+
+    /// Records the events between the last basins and `basins` at `time` hours of rain
+    fn compare(&mut self, basins: Vec<Basin<T>>, time: f32) {
+        let first = self.events.len();
+
+        for basin in &basins {
+            let before = self
+                .basins
+                .iter()
+                .filter(|old| old.start <= basin.end && basin.start <= old.end)
+                .copied()
+                .collect::<Vec<Basin<T>>>();
+
+            match before.len() {
+                0 => self.push(time, EventKind::BasinStarted, basin.start, basin.end),
+                1 => {}
+                _ => self.push(time, EventKind::BasinsMerged, basin.start, basin.end),
+            }
+            let was_full = before.len() == 1 && before[0].is_full();
+            if basin.is_full() && !was_full {
+                self.push(time, EventKind::BasinFull, basin.start, basin.end);
+            }
+        }
+
+        for i in 0..self.peaks.len() {
+            let peak = self.peaks[i];
+            let submerged = basins
+                .iter()
+                .any(|basin| basin.start <= peak.end && peak.start <= basin.end);
+            if submerged && !peak.submerged {
+                self.push(time, EventKind::PeakSubmerged, peak.start, peak.end);
+            }
+            self.peaks[i].submerged = submerged;
+        }
+
+        // Report the events of the same moment from left to right
+        self.events[first..].sort_by_key(|event| event.start);
+        self.basins = basins;
+    }
+
+    fn push(&mut self, time: f32, kind: EventKind, start: usize, end: usize) {
+        self.events.push(Event {
+            time,
+            kind,
+            start,
+            end,
+        });
+    }
+}
+
+/// Returns `true` if the `basins` cover other columns than the basins `before`, or are full where they were not
+fn changed<T: Number>(before: &[Basin<T>], basins: &[Basin<T>]) -> bool {
+    before.len() != basins.len()
+        || before.iter().zip(basins).any(|(before, basin)| {
+            (before.start, before.end, before.is_full())
+                != (basin.start, basin.end, basin.is_full())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::Boundary;
+    use crate::simulation::Simulation;
+    use assert_approx_eq::assert_approx_eq as approx_eq;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_peaks() {
+        let env = Environment::new(vec![5, 1, 3, 3, 1, 4, 2]).unwrap();
+        let log = EventLog::new(&env);
+
+        let peaks = log
+            .peaks
+            .iter()
+            .map(|peak| (peak.start, peak.end))
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(peaks, vec![(3, 4), (6, 6)]);
+    }
+
+    #[test]
+    fn test_peaks_on_the_ends() {
+        let env = Environment::new(vec![5, 1, 3, 3, 1, 4, 6])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Open)
+            .unwrap();
+        let log = EventLog::new(&env);
+
+        let peaks = log
+            .peaks
+            .iter()
+            .map(|peak| (peak.start, peak.end))
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(peaks, vec![(1, 1), (3, 4), (7, 7)]);
+    }
+
+    #[test]
+    fn test_onset_within_step() {
+        // The pit fills up after 1.5 hours, in the middle of the second step
+        let env = Environment::new(vec![4, 1, 4])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Open)
+            .unwrap();
+        let mut simulation = Simulation::new(env);
+        simulation.run(2., 1.).unwrap();
+
+        let full = simulation
+            .events()
+            .iter()
+            .filter(|event| event.kind == EventKind::BasinFull)
+            .collect::<Vec<&Event>>();
+        assert_eq!(full.len(), 1);
+        approx_eq!(full[0].time, 1.5);
+    }
+
+    #[test]
+    fn test_rain_with_events() {
+        // The right pit fills up to the peak after 0.4 hours and then overflows into the left one, which reaches
+        // the peak after 0.6 hours, all within a single rain
+        let mut env = Environment::new(vec![5, 1, 3, 2, 5]).unwrap();
+        let events = env.rain_with_events(1.).unwrap();
+
+        let onset = |kind: EventKind, start: usize, end: usize| {
+            events
+                .iter()
+                .find(|event| (event.kind, event.start, event.end) == (kind, start, end))
+                .map(|event| event.time)
+                .unwrap()
+        };
+        approx_eq!(onset(EventKind::BasinStarted, 2, 2), 0.);
+        approx_eq!(onset(EventKind::BasinStarted, 4, 4), 0.);
+        approx_eq!(onset(EventKind::BasinFull, 4, 4), 0.4);
+        approx_eq!(onset(EventKind::BasinsMerged, 2, 4), 0.6);
+        approx_eq!(onset(EventKind::PeakSubmerged, 3, 3), 0.6);
+        approx_eq!(env.water_level(3), 3.6666667);
+    }
+
+    #[test]
+    fn test_few_rains_per_onset() {
+        // The three transitions of the rain take a handful of untraced rains to place, not one per bit of `f32`
+        let mut before = Environment::new(vec![5, 1, 3, 2, 5]).unwrap();
+        before.start_trace();
+        let mut after = before.clone();
+        after.rain(1.).unwrap();
+        let mut log = EventLog::new(&before);
+        let rains = Cell::new(0);
+        log.record(&before, &after, 0., 1., |env, hours| {
+            assert!(env.trace().is_none());
+            rains.set(rains.get() + 1);
+            env.rain(hours).map(|_| ())
+        })
+        .unwrap();
+
+        assert_eq!(log.events().len(), 5);
+        approx_eq!(log.events()[2].time, 0.4);
+        approx_eq!(log.events()[4].time, 0.6);
+        assert!(rains.get() <= 12, "{} rains", rains.get());
+    }
+
+    #[test]
+    fn test_rain_with_events_in_f64() {
+        let mut env = Environment::<f64>::from_relief(vec![5, 1, 3, 2, 5]).unwrap();
+        let events = env.rain_with_events(1.).unwrap();
+
+        assert_eq!(events.len(), 5);
+        approx_eq!(events[2].time, 0.4);
+        approx_eq!(env.water_level(3), 11. / 3.);
+    }
+
+    #[test]
+    fn test_no_full_basin_on_the_slope() {
+        // The pit spreads onto the column of 2 on its way up to the column of 3, which it overflows after 6/7 hours
+        let env = Environment::new(vec![5, 1, 2, 3])
+            .unwrap()
+            .with_boundaries(Boundary::Wall, Boundary::Open)
+            .unwrap();
+        let mut simulation = Simulation::new(env);
+        simulation.run(2., 0.5).unwrap();
+
+        let events = simulation
+            .events()
+            .iter()
+            .map(|event| (event.kind, event.start, event.end))
+            .collect::<Vec<(EventKind, usize, usize)>>();
+        assert_eq!(
+            events,
+            vec![
+                (EventKind::BasinStarted, 2, 2),
+                (EventKind::BasinFull, 2, 3)
+            ]
+        );
+        approx_eq!(simulation.events()[1].time, 6. / 7.);
+    }
+}
@@ -0,0 +1,774 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::f32;
+
+use crate::balance::MassBalance;
+use crate::boundary::Boundary;
+use crate::env::Column;
+use crate::error::EnvError;
+use crate::number::{compensated_sum, Number};
+
+/// Which cells of the `Grid` are neighbours, so the water can flow between them
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Cells sharing an edge
+    #[default]
+    Four,
+    /// Cells sharing an edge or a corner
+    Eight,
+}
+
+impl Connectivity {
+    /// Returns the row and column offsets of the neighbours of a cell
+    fn offsets(self) -> &'static [(isize, isize)] {
+        match self {
+            Connectivity::Four => &[(-1, 0), (0, -1), (0, 1), (1, 0)],
+            Connectivity::Eight => &[
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ],
+        }
+    }
+}
+
+/// A neighbour of a cell, either another cell or the border of the grid
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Neighbour {
+    Cell(usize),
+    Border,
+}
+
+/// An edge from a region of the `Grid` to one of its neighbours, with the water level of the neighbour
+///
+/// Rims are ordered the other way round, so that a `BinaryHeap` returns the lowest one first.
+#[derive(Copy, Clone, Debug)]
+struct Rim {
+    level: f32,
+    neighbour: Neighbour,
+}
+
+impl PartialEq for Rim {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Rim {}
+
+impl PartialOrd for Rim {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rim {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.level.total_cmp(&self.level)
+    }
+}
+
+/// Cells of the `Grid` connected at the same water level, a lake or a plateau
+#[derive(Debug, Default)]
+struct Region {
+    level: f32,
+    cells: usize,
+    /// Total height of the cells and units of water standing on them, which keep the level of a rising lake exact
+    height: f64,
+    water: f64,
+    /// Edges to the neighbours that were not lower than the region when they were looked at
+    rims: BinaryHeap<Rim>,
+    /// Edges to lower neighbours, the water that reaches the region runs off over them
+    outlets: Vec<Neighbour>,
+    /// Units of water that have reached the region and are not placed yet
+    pending: f64,
+}
+
+/// Two dimensional version of the `Environment`
+///
+/// The Grid consists of rows × cols `Columns` stored row by row and a rain bank with one value for every
+/// cell. The whole grid is enclosed by the same `Boundary` on every side.
+///
+/// The rain runs down the relief and splits evenly over all the edges from the cell, or the plateau, it is on to
+/// lower neighbours, like on a peak of the `Environment`. In a pit it fills up a lake, which spills over its lowest
+/// neighbours once it is full. Water that reaches an open border leaves the grid.
+///
+/// A single row of cells between walls matches the `Environment`, except where full lakes meet rims of the
+/// same height. The grid merges such a row of lakes with its rims into one flat region, which spills evenly
+/// over both ends, while the `Environment` passes the water on pool by pool. A lake in the grid has no
+/// direction that the water crossing one of its rims keeps, so the grid does not follow the `Environment`
+/// there.
+///
+/// Unlike the `Environment`, the grid is not generic over the `Number` type and always stores `f32`. Its lakes
+/// add up their heights and water in `f64` instead, as the capacity of a large lake is the difference of two
+/// large sums.
+#[derive(Debug)]
+pub struct Grid {
+    rows: usize,
+    cols: usize,
+    cells: Vec<Column>,
+    rain: Vec<f32>,
+    connectivity: Connectivity,
+    border: Boundary,
+    /// Total units of rain that have fallen on the grid
+    rain_in: f32,
+    /// Total units of water that have left the grid over the border
+    outflow: f32,
+    /// The regions of the water surface while the rain is placed, as a union-find over the cells
+    parents: Vec<usize>,
+    regions: Vec<Region>,
+}
+
+impl Grid {
+    /// Constructs a new `Grid` with `rows` rows and `cols` columns from the heights stored row by row
+    ///
+    /// Fails if the grid has no cells, the number of heights does not match or a height is too large to be
+    /// stored exactly.
+    pub fn new(rows: usize, cols: usize, heights: Vec<u32>) -> Result<Self, EnvError> {
+        if let Some(height) = heights
+            .iter()
+            .find(|height| **height > <f32 as Number>::MAX_HEIGHT)
+        {
+            return Err(EnvError::InvalidRelief(format!(
+                "height {} is too large to be stored exactly",
+                height
+            )));
+        }
+
+        Self::from_heights(
+            rows,
+            cols,
+            heights.into_iter().map(|height| height as f32).collect(),
+        )
+    }
+
+    /// Constructs a new `Grid` from real valued heights, which may also be negative
+    ///
+    /// Fails if the grid has no cells, the number of heights does not match or a height is not a finite
+    /// number or further from 0 than `MAX_HEIGHT`.
+    pub fn from_heights(rows: usize, cols: usize, heights: Vec<f32>) -> Result<Self, EnvError> {
+        if rows == 0 || cols == 0 {
+            return Err(EnvError::InvalidRelief("the grid has no cells".to_string()));
+        }
+        if heights.len() != rows * cols {
+            return Err(EnvError::InvalidRelief(format!(
+                "expected {} heights for a {}x{} grid, found {}",
+                rows * cols,
+                rows,
+                cols,
+                heights.len()
+            )));
+        }
+        if let Some(height) = heights.iter().find(|height| !height.is_finite()) {
+            return Err(EnvError::InvalidRelief(format!(
+                "height {} is not a finite number",
+                height
+            )));
+        }
+        let max = <f32 as Number>::MAX_HEIGHT;
+        if let Some(height) = heights.iter().find(|height| height.abs() > max as f32) {
+            return Err(EnvError::InvalidRelief(format!(
+                "height {} is further from 0 than {}",
+                height, max
+            )));
+        }
+
+        Ok(Self {
+            rows,
+            cols,
+            cells: heights.into_iter().map(Column::new).collect(),
+            rain: vec![0.; rows * cols],
+            connectivity: Connectivity::Four,
+            border: Boundary::Wall,
+            rain_in: 0.,
+            outflow: 0.,
+            parents: Vec::new(),
+            regions: Vec::new(),
+        })
+    }
+
+    /// Sets which cells are neighbours
+    pub fn with_connectivity(mut self, connectivity: Connectivity) -> Self {
+        self.connectivity = connectivity;
+        self
+    }
+
+    /// Sets the border around the whole grid
+    ///
+    /// Fails if the height of a wall is not finite.
+    pub fn with_border(mut self, border: Boundary) -> Result<Self, EnvError> {
+        border.validate::<f32>()?;
+        self.border = border;
+        Ok(self)
+    }
+
+    /// Returns the number of rows
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the number of columns
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Returns the cell in `row` and `col`, both starting with 0
+    pub fn cell(&self, row: usize, col: usize) -> &Column {
+        &self.cells[self.index(row, col)]
+    }
+
+    /// Returns the water level of the cell in `row` and `col`
+    pub fn water_level(&self, row: usize, col: usize) -> f32 {
+        self.cell(row, col).water_level()
+    }
+
+    /// Returns the depth of the water above the cell in `row` and `col`
+    pub fn water_depth(&self, row: usize, col: usize) -> f32 {
+        self.cell(row, col).water()
+    }
+
+    /// Returns the units of water that have left the grid over the border
+    pub fn outflow(&self) -> f32 {
+        self.outflow
+    }
+
+    /// Returns the mass balance of all the rain that has fallen on the grid
+    ///
+    /// The cells are stored row by row and all the water that has left over the border counts as
+    /// `outflow_left`, as the grid has the same border on every side.
+    pub fn mass_balance(&self) -> MassBalance {
+        MassBalance {
+            rain_in: self.rain_in,
+            stored: self.cells.iter().map(Column::water).collect(),
+            leftover: 0.,
+            evaporated: 0.,
+            infiltrated: 0.,
+            outflow_left: self.outflow,
+            outflow_right: 0.,
+        }
+    }
+
+    /// Accepts the number of hours it has rain and mutates the grid to its endstate.
+    ///
+    /// The same as `Environment::rain`, every hour every cell gets 1 unit of rain. Returns the water that
+    /// could not be placed, which is always 0.
+    pub fn rain(&mut self, rain_hours: f32) -> Result<f32, EnvError> {
+        self.rain_profile(&vec![rain_hours; self.cells.len()])
+    }
+
+    /// Rains a different amount of water on every cell and mutates the grid to its endstate.
+    ///
+    /// The profile has one value for every cell, stored row by row.
+    pub fn rain_profile(&mut self, profile: &[f32]) -> Result<f32, EnvError> {
+        if profile.len() != self.cells.len() {
+            return Err(EnvError::ProfileLength {
+                expected: self.cells.len(),
+                found: profile.len(),
+            });
+        }
+        if let Some(rain) = profile.iter().find(|rain| !rain.is_finite() || **rain < 0.) {
+            return Err(EnvError::InvalidRain(*rain));
+        }
+
+        self.rain = profile.to_vec();
+        self.rain_in += compensated_sum(profile.iter().copied());
+        self.find_regions();
+
+        let mut queue = BinaryHeap::new();
+        for cell in 0..self.cells.len() {
+            let rain_water = self.new_rain(cell);
+            if rain_water > 0. {
+                self.receive(cell, rain_water as f64, &mut queue);
+            }
+        }
+
+        // Place the water from the highest region down, so that a region only passes its water on once
+        // everything that can run into it has arrived
+        let mut outflow = 0.;
+        while let Some(Reverse(next)) = queue.pop() {
+            if let Neighbour::Cell(region) = next.neighbour {
+                outflow += self.place(region, &mut queue);
+            }
+        }
+        self.outflow += outflow as f32;
+
+        for cell in 0..self.cells.len() {
+            let region = self.find(cell);
+            let level = self.regions[region].level;
+            if level > self.level(cell) {
+                self.cells[cell].fill_to(level);
+            }
+        }
+        self.parents.clear();
+        self.regions.clear();
+        Ok(0.)
+    }
+
+    /// Grabs the rain from the rain bank, draining it
+    fn new_rain(&mut self, cell: usize) -> f32 {
+        std::mem::replace(&mut self.rain[cell], 0.)
+    }
+
+    /// Splits the grid into regions, the parts of the water surface connected at the same level
+    fn find_regions(&mut self) {
+        self.parents = (0..self.cells.len()).collect();
+        self.regions = self
+            .cells
+            .iter()
+            .map(|cell| Region {
+                level: cell.water_level(),
+                cells: 1,
+                height: cell.height() as f64,
+                water: cell.water() as f64,
+                ..Region::default()
+            })
+            .collect();
+
+        for cell in 0..self.cells.len() {
+            for neighbour in self.neighbours(cell) {
+                if let Neighbour::Cell(neighbour) = neighbour {
+                    let (region, other) = (self.find(cell), self.find(neighbour));
+                    if region != other && self.level(cell) == self.level(neighbour) {
+                        self.merge(region, other);
+                    }
+                }
+            }
+        }
+
+        for cell in 0..self.cells.len() {
+            let region = self.find(cell);
+            for neighbour in self.neighbours(cell) {
+                if self.region_of(neighbour) != Some(region) {
+                    let level = self.neighbour_level(neighbour);
+                    self.regions[region].rims.push(Rim { level, neighbour });
+                }
+            }
+        }
+    }
+
+    /// Adds `water` units of water to the region of the `cell`, which queues the region if it has none yet
+    fn receive(&mut self, cell: usize, water: f64, queue: &mut BinaryHeap<Reverse<Rim>>) {
+        let region = self.find(cell);
+        if self.regions[region].pending == 0. {
+            queue.push(Reverse(Rim {
+                level: self.regions[region].level,
+                neighbour: Neighbour::Cell(region),
+            }));
+        }
+        self.regions[region].pending += water;
+    }
+
+    /// Places the water that has reached the `region` and returns the units that have left the grid
+    ///
+    /// The water runs off evenly over all the edges to lower neighbours, like on a peak of the `Environment`.
+    /// If there are none, the region is a lake, which rises up to its lowest rim, merges with the regions at
+    /// that level and passes the rest of the water on from there.
+    fn place(&mut self, region: usize, queue: &mut BinaryHeap<Reverse<Rim>>) -> f64 {
+        let mut region = self.find(region);
+        let mut water = std::mem::take(&mut self.regions[region].pending);
+        if water == 0. {
+            return 0.;
+        }
+
+        loop {
+            let outlets = self.outlets(region);
+            if !outlets.is_empty() {
+                let share = water / outlets.len() as f64;
+                let mut outflow = 0.;
+                for outlet in &outlets {
+                    match *outlet {
+                        Neighbour::Border => outflow += share,
+                        Neighbour::Cell(cell) => self.receive(cell, share, queue),
+                    }
+                }
+                self.regions[region].outlets = outlets;
+                return outflow;
+            }
+
+            let spill = self.lowest_rim(region).map_or(f32::MAX, |rim| rim.level);
+            let lake = &mut self.regions[region];
+            let cells = lake.cells as f64;
+            let capacity = spill as f64 * cells - lake.height - lake.water;
+            let level = ((lake.height + lake.water + water) / cells) as f32;
+            if water < capacity && level < spill {
+                lake.water += water;
+                lake.level = lake.level.max(level);
+                return 0.;
+            }
+
+            // The lake is full, the rest of the water spills over all its rims at the same level
+            water = (water - capacity).max(0.);
+            lake.water = spill as f64 * cells - lake.height;
+            lake.level = spill;
+            while let Some(rim) = self.lowest_rim(region).filter(|rim| rim.level == spill) {
+                self.regions[region].rims.pop();
+                match rim.neighbour {
+                    Neighbour::Border => self.regions[region].outlets.push(Neighbour::Border),
+                    Neighbour::Cell(cell) => {
+                        let other = self.find(cell);
+                        region = self.merge(region, other);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the edges of the `region` to lower neighbours, or to a border that is not higher
+    fn outlets(&mut self, region: usize) -> Vec<Neighbour> {
+        let level = self.regions[region].level;
+        let mut outlets = std::mem::take(&mut self.regions[region].outlets);
+        outlets.retain(|outlet| self.region_of(*outlet) != Some(region));
+
+        while let Some(rim) = self.lowest_rim(region) {
+            if rim.level > level || (rim.level == level && rim.neighbour != Neighbour::Border) {
+                break;
+            }
+            self.regions[region].rims.pop();
+            outlets.push(rim.neighbour);
+        }
+        outlets
+    }
+
+    /// Returns the lowest rim of the `region`
+    ///
+    /// Edges that have come to lie inside the region are dropped and the levels of the others are brought up to
+    /// date first, as the neighbours may have risen since they were looked at.
+    fn lowest_rim(&mut self, region: usize) -> Option<Rim> {
+        while let Some(rim) = self.regions[region].rims.peek().copied() {
+            let level = match rim.neighbour {
+                Neighbour::Border => return Some(rim),
+                Neighbour::Cell(cell) => {
+                    let other = self.find(cell);
+                    if other == region {
+                        self.regions[region].rims.pop();
+                        continue;
+                    }
+                    self.regions[other].level
+                }
+            };
+            if level == rim.level {
+                return Some(rim);
+            }
+            let rims = &mut self.regions[region].rims;
+            rims.pop();
+            rims.push(Rim { level, ..rim });
+        }
+        None
+    }
+
+    /// Merges two regions at the same level and returns the one that is left
+    fn merge(&mut self, region: usize, other: usize) -> usize {
+        let (region, other) = if self.regions[region].cells >= self.regions[other].cells {
+            (region, other)
+        } else {
+            (other, region)
+        };
+        let mut merged = std::mem::take(&mut self.regions[other]);
+        let into = &mut self.regions[region];
+        into.cells += merged.cells;
+        into.height += merged.height;
+        into.water += merged.water;
+        into.pending += merged.pending;
+        into.rims.append(&mut merged.rims);
+        into.outlets.append(&mut merged.outlets);
+        self.parents[other] = region;
+        region
+    }
+
+    /// Returns the region the `cell` belongs to
+    fn find(&mut self, mut cell: usize) -> usize {
+        while self.parents[cell] != cell {
+            self.parents[cell] = self.parents[self.parents[cell]];
+            cell = self.parents[cell];
+        }
+        cell
+    }
+
+    /// Returns the region of a neighbour, or `None` for the border
+    fn region_of(&mut self, neighbour: Neighbour) -> Option<usize> {
+        match neighbour {
+            Neighbour::Cell(cell) => Some(self.find(cell)),
+            Neighbour::Border => None,
+        }
+    }
+
+    /// Returns the neighbours of the `cell`
+    fn neighbours(&self, cell: usize) -> impl Iterator<Item = Neighbour> {
+        let (rows, cols) = (self.rows as isize, self.cols as isize);
+        let (row, col) = ((cell / self.cols) as isize, (cell % self.cols) as isize);
+        self.connectivity.offsets().iter().map(move |(dr, dc)| {
+            let (row, col) = (row + dr, col + dc);
+            if row < 0 || col < 0 || row >= rows || col >= cols {
+                Neighbour::Border
+            } else {
+                Neighbour::Cell((row * cols + col) as usize)
+            }
+        })
+    }
+
+    /// Returns the water level of a neighbour
+    fn neighbour_level(&self, neighbour: Neighbour) -> f32 {
+        match neighbour {
+            Neighbour::Cell(cell) => self.level(cell),
+            Neighbour::Border => self.border.height(),
+        }
+    }
+
+    fn level(&self, cell: usize) -> f32 {
+        self.cells[cell].water_level()
+    }
+
+    fn index(&self, row: usize, col: usize) -> usize {
+        assert!(row < self.rows && col < self.cols, "cell out of the grid");
+        row * self.cols + col
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::BALANCE_TOLERANCE;
+    use crate::env::Environment;
+    use assert_approx_eq::assert_approx_eq as approx_eq;
+    use proptest::prelude::*;
+
+    fn pit(center: u32) -> Vec<u32> {
+        vec![5, 5, 5, 5, center, 5, 5, 5, 5]
+    }
+
+    #[test]
+    fn test_invalid_grid() {
+        assert!(matches!(
+            Grid::new(0, 3, vec![]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Grid::new(2, 2, vec![1, 2, 3]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Grid::from_heights(1, 2, vec![1., f32::INFINITY]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+        assert!(matches!(
+            Grid::from_heights(1, 2, vec![1., -1e9]),
+            Err(EnvError::InvalidRelief(_))
+        ));
+    }
+
+    #[test]
+    fn test_pit_with_walls() {
+        let mut grid = Grid::new(3, 3, pit(0)).unwrap();
+
+        let backwater = grid.rain(1.0).unwrap();
+        approx_eq!(backwater, 0.);
+
+        approx_eq!(grid.water_level(1, 1), 5.444444);
+        approx_eq!(grid.water_level(0, 0), 5.444444);
+        approx_eq!(grid.water_depth(1, 1), 5.444444);
+        approx_eq!(grid.outflow(), 0.);
+        approx_eq!(grid.mass_balance().residual(), 0., 1e-5);
+    }
+
+    #[test]
+    fn test_pit_with_open_border() {
+        let mut grid = Grid::new(3, 3, pit(0))
+            .unwrap()
+            .with_border(Boundary::Open)
+            .unwrap();
+        grid.rain(1.0).unwrap();
+
+        // The ring runs off evenly over its 12 edges to the border and its 4 edges to the pit
+        approx_eq!(grid.water_level(1, 1), 3.);
+        approx_eq!(grid.water_depth(0, 1), 0.);
+        approx_eq!(grid.outflow(), 6.);
+        approx_eq!(grid.mass_balance().residual(), 0.);
+    }
+
+    #[test]
+    fn test_pit_with_low_walls() {
+        let mut grid = Grid::new(3, 3, pit(0))
+            .unwrap()
+            .with_border(Boundary::Height(5.25))
+            .unwrap();
+        grid.rain(1.0).unwrap();
+
+        approx_eq!(grid.water_level(1, 1), 5.25);
+        approx_eq!(grid.water_level(2, 2), 5.25);
+        approx_eq!(grid.outflow(), 1.75);
+        approx_eq!(grid.mass_balance().residual(), 0., 1e-5);
+    }
+
+    #[test]
+    fn test_connectivity() {
+        let heights = vec![0, 5, 5, 5, 1, 5, 5, 5, 5];
+
+        let mut grid = Grid::new(3, 3, heights.clone())
+            .unwrap()
+            .with_border(Boundary::Open)
+            .unwrap();
+        grid.rain(1.0).unwrap();
+        // 4 of the 16 edges the ring of 5s runs off over lead to the middle, 2 to the corner and 10 to the border
+        approx_eq!(grid.water_depth(1, 1), 2.75);
+
+        let mut grid = Grid::new(3, 3, heights)
+            .unwrap()
+            .with_connectivity(Connectivity::Eight)
+            .with_border(Boundary::Open)
+            .unwrap();
+        grid.rain(1.0).unwrap();
+        approx_eq!(grid.water_depth(1, 1), 0.);
+        approx_eq!(grid.outflow(), 9.);
+    }
+
+    #[test]
+    fn test_lakes_merge() {
+        // Two pits separated by a ridge of 3 in a basin of 6
+        let heights = vec![
+            6, 6, 6, 6, 6, //
+            6, 0, 3, 1, 6, //
+            6, 6, 6, 6, 6, //
+        ];
+        let mut grid = Grid::new(3, 5, heights).unwrap();
+        grid.rain_profile(&[
+            0., 0., 0., 0., 0., //
+            0., 5., 0., 0., 0., //
+            0., 0., 0., 0., 0., //
+        ])
+        .unwrap();
+
+        approx_eq!(grid.water_level(1, 1), 3.);
+        approx_eq!(grid.water_level(1, 3), 3.);
+        approx_eq!(grid.water_depth(1, 2), 0.);
+    }
+
+    #[test]
+    fn test_row_of_cells_matches_environment() {
+        let mut grid = Grid::new(1, 5, vec![3, 7, 4, 5, 3]).unwrap();
+        grid.rain_profile(&[1., 0., 0., 0., 2.]).unwrap();
+
+        let mut env = Environment::new(vec![3, 7, 4, 5, 3]).unwrap();
+        env.rain_profile(&[1., 0., 0., 0., 2.]).unwrap();
+
+        for col in 0..5 {
+            approx_eq!(grid.water_level(0, col), env.water_level(col + 1));
+        }
+    }
+
+    #[test]
+    fn test_row_of_cells_splits_on_peaks() {
+        let reliefs = [
+            vec![1, 4, 2],
+            vec![2, 6, 1, 5, 3, 7, 0, 4],
+            vec![3, 1, 4, 1, 5, 9, 2, 6],
+            vec![1, 5, 5, 2, 6, 0, 3],
+        ];
+        for relief in reliefs {
+            for hours in [0.5, 1., 2.] {
+                let mut grid = Grid::new(1, relief.len(), relief.clone()).unwrap();
+                grid.rain(hours).unwrap();
+                let mut env = crate::env::Environment::new(relief.clone()).unwrap();
+                env.rain(hours).unwrap();
+
+                for col in 0..relief.len() {
+                    approx_eq!(grid.water_level(0, col), env.water_level(col + 1));
+                }
+            }
+        }
+
+        let mut grid = Grid::new(1, 3, vec![1, 4, 2]).unwrap();
+        grid.rain(1.0).unwrap();
+        approx_eq!(grid.water_level(0, 0), 2.5);
+        approx_eq!(grid.water_level(0, 2), 3.5);
+    }
+
+    #[test]
+    fn test_row_of_cells_spills_evenly_over_equal_rims() {
+        // The full pools between the 5s spill as one flat region, half of the water over each end
+        let relief = vec![1, 5, 4, 5, 3, 5, 5, 1];
+        let mut grid = Grid::new(1, relief.len(), relief.clone()).unwrap();
+        grid.rain(1.0).unwrap();
+        approx_eq!(grid.water_level(0, 0), 3.5);
+        approx_eq!(grid.water_level(0, 7), 3.5);
+
+        // The environment passes the water on pool by pool, so more of it reaches the nearer end
+        let mut env = Environment::new(relief).unwrap();
+        env.rain(1.0).unwrap();
+        approx_eq!(env.water_level(1), 3.333333);
+        approx_eq!(env.water_level(8), 3.666667);
+    }
+
+    #[test]
+    fn test_large_bowl() {
+        // A 200x200 cone, each lake is found without searching it again for every cell poured into it
+        let size = 200;
+        let heights = (0..size * size)
+            .map(|cell| {
+                ((cell / size) as i32 - 100).unsigned_abs()
+                    + ((cell % size) as i32 - 100).unsigned_abs()
+            })
+            .collect();
+        let mut grid = Grid::new(size, size, heights).unwrap();
+        grid.rain(10.0).unwrap();
+
+        assert_eq!(grid.water_level(100, 100), grid.water_level(100, 150));
+        approx_eq!(grid.water_depth(100, 100), grid.water_level(100, 100));
+        approx_eq!(grid.outflow(), 0.);
+        let balance = grid.mass_balance();
+        assert!(balance.residual().abs() <= 1e-6 * balance.rain_in);
+        assert!(balance.check_relative(BALANCE_TOLERANCE).is_ok());
+    }
+
+    /// Whether two rims of the `relief` between walls have the same height, the tops of the runs of
+    /// columns that are higher than the columns next to them
+    fn equal_rims(relief: &[u32]) -> bool {
+        let mut heights = vec![u32::MAX];
+        heights.extend(relief);
+        heights.push(u32::MAX);
+
+        let mut rims = vec![];
+        let mut pos = 1;
+        while pos < heights.len() - 1 {
+            let start = pos;
+            while heights[pos] == heights[start] {
+                pos += 1;
+            }
+            if heights[start - 1] < heights[start] && heights[pos] < heights[start] {
+                rims.push(heights[start]);
+            }
+        }
+        rims.sort_unstable();
+        rims.windows(2).any(|pair| pair[0] == pair[1])
+    }
+
+    proptest! {
+        #[test]
+        fn test_row_of_cells_against_environment(
+            relief in prop::collection::vec(0..8u32, 1..12)
+                .prop_filter("rims of the same height", |relief| !equal_rims(relief)),
+            eighths in 1..40u32,
+        ) {
+            let hours = eighths as f32 / 8.;
+            let mut grid = Grid::new(1, relief.len(), relief.clone()).unwrap();
+            grid.rain(hours).unwrap();
+            let mut env = Environment::new(relief.clone()).unwrap();
+            env.rain(hours).unwrap();
+
+            for col in 0..relief.len() {
+                let (grid_level, env_level) = (grid.water_level(0, col), env.water_level(col + 1));
+                prop_assert!(
+                    (grid_level - env_level).abs() <= 1e-4 * env_level.max(1.),
+                    "column {}: grid {} but environment {}",
+                    col,
+                    grid_level,
+                    env_level
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Simulation of rain falling on a relief.
+//!
+//! The relief is a list of column heights, enclosed by two infinite walls. Rain falls on every column
+//! and flows down the relief until it settles in the valleys. The heights and the water are stored as `f32`
+//! unless another `Number` type is chosen, like the exact `Rational`, which carries over to the `Simulation`,
+//! its `Snapshots` and `Replay`. Only the two dimensional `Grid` always stores `f32`.
+//!
+//! ```
+//! use water_level::Environment;
+//!
+//! let mut env = Environment::new(vec![3, 1, 2]).unwrap();
+//! env.rain(1.0).unwrap();
+//!
+//! assert_eq!(env.water_level(2), 3.0);
+//! assert_eq!(env.water_depth(3), 1.0);
+//! ```
+
+mod balance;
+mod basin;
+mod batch;
+mod boundary;
+mod env;
+mod error;
+mod events;
+mod grid;
+mod losses;
+mod number;
+#[cfg(test)]
+mod reference;
+mod render;
+mod replay;
+mod scenario;
+mod schedule;
+mod simulation;
+mod trace;
+
+pub use balance::{Imbalance, MassBalance, BALANCE_TOLERANCE};
+pub use basin::{Basin, Spill};
+pub use batch::{
+    parse_numbers, read_jobs, run_batch, run_stream, BatchFormat, BatchWriter, Job, Outcome,
+};
+pub use boundary::Boundary;
+pub use env::{Column, Environment};
+pub use error::EnvError;
+pub use events::{Event, EventKind};
+pub use grid::{Connectivity, Grid};
+pub use losses::{Losses, Soil};
+pub use number::{Number, Rational};
+pub use render::{render_ascii, render_pixels, render_png, render_svg, render_terminal};
+pub use replay::Replay;
+pub use scenario::Scenario;
+pub use schedule::{Intensity, RainSchedule, Segment};
+pub use simulation::{Simulation, Snapshot};
+pub use trace::{Action, Handler, Resume, Trace, TraceStep};
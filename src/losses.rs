@@ -0,0 +1,109 @@
+use crate::error::EnvError;
+
+/// Type of the soil under a column, which decides how fast the water infiltrates into the ground
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Soil {
+    /// Impermeable rock, no water infiltrates
+    Rock,
+    Clay,
+    Loam,
+    Sand,
+}
+
+impl Soil {
+    /// Returns the infiltration rate of the soil in units of water per hour
+    pub fn infiltration(self) -> f32 {
+        match self {
+            Soil::Rock => 0.,
+            Soil::Clay => 0.02,
+            Soil::Loam => 0.1,
+            Soil::Sand => 0.5,
+        }
+    }
+}
+
+/// The ways water leaves the `Environment` over time
+///
+/// Water evaporates from every column covered by water at the same rate and infiltrates into the ground at
+/// the rate of the column. Both rates are in units of water per hour.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Losses {
+    /// Evaporation rate from the water surface
+    pub evaporation: f32,
+    /// Infiltration rate of every column of the relief, from left to right. Empty if there is no infiltration.
+    pub infiltration: Vec<f32>,
+}
+
+impl Losses {
+    /// Constructs new `Losses` with only evaporation
+    pub fn new(evaporation: f32) -> Self {
+        Self {
+            evaporation,
+            infiltration: vec![],
+        }
+    }
+
+    /// Sets the infiltration rate of every column from the soil under it
+    pub fn with_soil(self, soil: &[Soil]) -> Self {
+        self.with_infiltration(soil.iter().map(|soil| soil.infiltration()).collect())
+    }
+
+    /// Sets the infiltration rate of every column
+    pub fn with_infiltration(mut self, infiltration: Vec<f32>) -> Self {
+        self.infiltration = infiltration;
+        self
+    }
+
+    /// Returns the infiltration rate of the column in position `pos`
+    pub fn infiltration(&self, pos: usize) -> f32 {
+        self.infiltration.get(pos - 1).copied().unwrap_or(0.)
+    }
+
+    /// Checks that all the rates are finite and non-negative and fit a relief of `columns` columns
+    pub(crate) fn validate(&self, columns: usize) -> Result<(), EnvError> {
+        if !self.infiltration.is_empty() && self.infiltration.len() != columns {
+            return Err(EnvError::ProfileLength {
+                expected: columns,
+                found: self.infiltration.len(),
+            });
+        }
+        match std::iter::once(&self.evaporation)
+            .chain(self.infiltration.iter())
+            .find(|rate| !rate.is_finite() || **rate < 0.)
+        {
+            Some(rate) => Err(EnvError::InvalidRate(*rate)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_soil() {
+        let losses = Losses::new(0.1).with_soil(&[Soil::Rock, Soil::Sand]);
+
+        assert_eq!(losses.infiltration(1), 0.);
+        assert_eq!(losses.infiltration(2), Soil::Sand.infiltration());
+        assert_eq!(Losses::new(0.1).infiltration(2), 0.);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Losses::new(0.1).validate(2).is_ok());
+        assert!(Losses::new(0.1)
+            .with_infiltration(vec![0., 1.])
+            .validate(2)
+            .is_ok());
+        assert!(matches!(
+            Losses::new(-0.1).validate(2),
+            Err(EnvError::InvalidRate(_))
+        ));
+        assert!(matches!(
+            Losses::new(0.1).with_infiltration(vec![1.]).validate(2),
+            Err(EnvError::ProfileLength { .. })
+        ));
+    }
+}
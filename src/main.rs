@@ -1,28 +1,492 @@
-use std::io::{self, BufRead};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-mod env;
+use serde::Serialize;
+use water_level::{
+    parse_numbers, read_jobs, render_ascii, render_png, render_svg, render_terminal, run_stream,
+    BatchFormat, BatchWriter, Column, EnvError, Environment, RainSchedule, Replay, Scenario,
+    Simulation, BALANCE_TOLERANCE,
+};
+
+mod cli;
+
+use cli::{Args, DataFormat, Mode, PictureFormat, ReplayFormat};
+
+/// Size of the PNG images, in pixels
+const IMAGE_WIDTH: u32 = 640;
+const IMAGE_HEIGHT: u32 = 320;
+
+/// Number of steps a replay takes if the step is not given
+const REPLAY_STEPS: f32 = 24.;
+
+/// Seconds every frame of a replay is shown if the delay is not given
+const REPLAY_DELAY: f32 = 0.25;
 
 fn main() {
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+
+    if let Err(err) = run(&args) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Runs the challenge. Everything that is not given in the arguments is asked for on the standard input.
+fn run(args: &Args) -> Result<(), EnvError> {
+    match args.mode {
+        Mode::Simulate(format) => {
+            let (env, schedule) = challenge(args)?;
+            simulate(args, env, &schedule, format)
+        }
+        Mode::Basins(format) => {
+            let (env, schedule) = challenge(args)?;
+            let mut out = output(args)?;
+            let env = rain(args, env, &schedule)?;
+            write_basins(&mut out, &env, format)?;
+            finish(&mut out, &env)
+        }
+        Mode::Render(format) => {
+            let (env, schedule) = challenge(args)?;
+            let mut out = output(args)?;
+            let env = rain(args, env, &schedule)?;
+            match format {
+                PictureFormat::Text => write!(out, "{}", render_ascii(&env))?,
+                PictureFormat::Svg => write!(out, "{}", render_svg(&env))?,
+                PictureFormat::Ansi => {
+                    let (width, height) = terminal_size();
+                    write!(out, "{}", render_terminal(&env, width, height))?
+                }
+                PictureFormat::Png => {
+                    out.write_all(&render_png(&env, IMAGE_WIDTH, IMAGE_HEIGHT)?)?
+                }
+            }
+            finish(&mut out, &env)
+        }
+        Mode::Batch(format) => batch(args, format),
+        Mode::Replay(format) => {
+            let (env, schedule) = challenge(args)?;
+            replay(args, env, &schedule, format)
+        }
+    }
+}
+
+/// Reads the relief and the rain of the challenge, from the scenario or from the other arguments
+fn challenge(args: &Args) -> Result<(Environment, RainSchedule), EnvError> {
+    let prompt = |message: &str| {
+        if !args.quiet {
+            println!("{}", message);
+        }
+    };
+
+    prompt("The Rain Challenge");
+    let (mut env, schedule) = match args.scenario.as_deref() {
+        Some(path) => {
+            let json = match path {
+                "-" => io::read_to_string(io::stdin())?,
+                path => fs::read_to_string(path)?,
+            };
+            let scenario = Scenario::from_json(&json)?;
+            (scenario.environment()?, scenario.rain)
+        }
+        None => read_challenge(args, &prompt)?,
+    };
+    if args.trace.is_some() {
+        env.start_trace();
+    }
+    Ok((env, schedule))
+}
+
+/// Rains the whole schedule at once, writing the trace even if the rain fails
+fn rain(
+    args: &Args,
+    mut env: Environment,
+    schedule: &RainSchedule,
+) -> Result<Environment, EnvError> {
+    let result = env.rain_schedule(schedule);
+    write_trace(args, &env)?;
+    result?;
+    Ok(env)
+}
+
+/// Rains on the relief and writes the endstate, or every snapshot if there is a step
+fn simulate(
+    args: &Args,
+    env: Environment,
+    schedule: &RainSchedule,
+    format: DataFormat,
+) -> Result<(), EnvError> {
+    let mut out = output(args)?;
+    let env = match args.step {
+        Some(step) => {
+            let mut simulation = Simulation::new(env);
+            let result = simulation.run_schedule(schedule, step).map(|_| ());
+            write_trace(args, simulation.environment())?;
+            result?;
+            write_snapshots(&mut out, &simulation, format)?;
+            simulation.into_environment()
+        }
+        None => {
+            let env = rain(args, env, schedule)?;
+            write_endstate(&mut out, &env, schedule.duration(), format)?;
+            env
+        }
+    };
+
+    if format == DataFormat::Text {
+        let balance = env.mass_balance();
+        writeln!(
+            out,
+            "Mass balance: {} units of rain, {} stored, {} left over",
+            balance.rain_in,
+            balance.total_stored(),
+            balance.leftover
+        )?;
+    }
+    finish(&mut out, &env)
+}
+
+/// Flushes the output and checks that the rain adds up with the water in the `Environment`
+fn finish(out: &mut dyn Write, env: &Environment) -> Result<(), EnvError> {
+    out.flush()?;
+    env.mass_balance().check_relative(BALANCE_TOLERANCE)?;
+    Ok(())
+}
+
+/// Simulates the rain step by step and plays the steps back in the terminal or writes them as images
+fn replay(
+    args: &Args,
+    env: Environment,
+    schedule: &RainSchedule,
+    format: ReplayFormat,
+) -> Result<(), EnvError> {
+    let step = args.step.unwrap_or(schedule.duration() / REPLAY_STEPS);
+    let mut simulation = Simulation::new(env);
+    let result = simulation.run_schedule(schedule, step).map(|_| ());
+    write_trace(args, simulation.environment())?;
+    result?;
+
+    let replay = Replay::new(&simulation);
+    let delay = args.delay.unwrap_or(REPLAY_DELAY);
+    if !delay.is_finite() || delay < 0. {
+        return Err(EnvError::Usage(format!(
+            "the delay of {} is not a finite, non-negative number of seconds",
+            delay
+        )));
+    }
+    let delay = Duration::from_secs_f32(delay);
+    match format {
+        ReplayFormat::Png => {
+            let directory = Path::new(args.output.as_deref().unwrap_or("."));
+            fs::create_dir_all(directory)?;
+            for (i, frame) in replay
+                .png_frames(IMAGE_WIDTH, IMAGE_HEIGHT)?
+                .iter()
+                .enumerate()
+            {
+                fs::write(directory.join(format!("frame-{:04}.png", i)), frame)?;
+            }
+        }
+        ReplayFormat::Apng => {
+            let mut out = output(args)?;
+            out.write_all(&replay.apng(IMAGE_WIDTH, IMAGE_HEIGHT, delay)?)?;
+            out.flush()?;
+        }
+        ReplayFormat::Text | ReplayFormat::Ansi => {
+            let (width, height) = terminal_size();
+            replay.play(&mut output(args)?, width, height, delay)?;
+        }
+    }
+
+    simulation
+        .environment()
+        .mass_balance()
+        .check_relative(BALANCE_TOLERANCE)?;
+    Ok(())
+}
+
+/// Runs every scenario of the input and writes one row of results for each, see `run_stream`
+fn batch(args: &Args, format: DataFormat) -> Result<(), EnvError> {
+    let input: Box<dyn Read> = match args.input.as_deref() {
+        None | Some("-") => Box::new(io::stdin()),
+        Some(path) => Box::new(File::open(path)?),
+    };
+    let format = match format {
+        DataFormat::Json => BatchFormat::Json,
+        DataFormat::Text | DataFormat::Csv => BatchFormat::Csv,
+    };
+    let mut out = BatchWriter::new(output(args)?, format)?;
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    run_stream(read_jobs(input)?, &mut out, threads)
+}
+
+/// Writes the trace of the flow algorithm as JSON, if it was asked for
+///
+/// The trace is written even if the rain has failed, it shows how the algorithm got there.
+fn write_trace(args: &Args, env: &Environment) -> Result<(), EnvError> {
+    if let (Some(path), Some(trace)) = (&args.trace, env.trace()) {
+        fs::write(path, trace.to_json() + "\n")?;
+    }
+    Ok(())
+}
+
+/// Returns the number of columns and rows of the terminal, leaving a row for the prompt
+///
+/// Falls back to the `COLUMNS` and `LINES` variables and then to 80 by 24 if the output is not a terminal.
+fn terminal_size() -> (usize, usize) {
+    let (width, height) = match terminal_size::terminal_size() {
+        Some((terminal_size::Width(width), terminal_size::Height(height))) => {
+            (width as usize, height as usize)
+        }
+        None => {
+            let variable = |name: &str, default: usize| {
+                std::env::var(name)
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(default)
+            };
+            (variable("COLUMNS", 80), variable("LINES", 24))
+        }
+    };
+    (width, height.saturating_sub(1))
+}
+
+/// Opens the file of the output, or the standard output if there is none
+fn output(args: &Args) -> Result<Box<dyn Write>, EnvError> {
+    Ok(match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    })
+}
+
+/// Reads the relief and the rain from the files in the arguments, or asks for them on the standard input
+fn read_challenge(
+    args: &Args,
+    prompt: &dyn Fn(&str),
+) -> Result<(Environment, RainSchedule), EnvError> {
     let stdin = io::stdin();
-    let mut iterator = stdin.lock().lines();
+    let mut lines = stdin.lock().lines();
+
+    let relief_input = match args.relief.as_deref() {
+        Some("-") => next_line(&mut lines, "the relief")?,
+        Some(path) => fs::read_to_string(path)?,
+        None => {
+            prompt("Please enter the relief as a space delimited list of heights:");
+            next_line(&mut lines, "the relief")?
+        }
+    };
+    let relief = parse_numbers(&relief_input, "a height")?;
+    let env = Environment::from_heights(relief)?;
+    prompt("Thank You!");
 
-    println!("The Rain Challenge");
-    println!("Please enter the relief as a space delimited list of integers:");
-    let relief_input = iterator.next().unwrap().unwrap();
+    let schedule = match (&args.profile, args.hours) {
+        (Some(path), _) => {
+            let profile = parse_numbers(&fs::read_to_string(path)?, "an amount of rain")?;
+            RainSchedule::new().then_profile(1., profile)
+        }
+        (None, Some(hours)) => RainSchedule::new().then(hours, 1.),
+        (None, None) => {
+            prompt("How many hours of rain will occour?");
+            let rain_input = next_line(&mut lines, "the hours of rain")?;
+            let hours = rain_input
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| EnvError::Parse {
+                    input: rain_input.clone(),
+                    expected: "a number of hours",
+                })?;
+            RainSchedule::new().then(hours, 1.)
+        }
+    };
 
-    let relief: Vec<u32> = relief_input
-        .split_whitespace()
-        .map(|col| col.parse::<u32>().unwrap())
-        .collect();
+    Ok((env, schedule))
+}
+
+/// Writes the endstate of the `Environment` after `hours` hours of rain
+fn write_endstate(
+    out: &mut dyn Write,
+    env: &Environment,
+    hours: f32,
+    format: DataFormat,
+) -> Result<(), EnvError> {
+    match format {
+        DataFormat::Text => writeln!(out, "{}", env)?,
+        DataFormat::Csv => {
+            writeln!(out, "{}", CSV_COLUMNS_HEADER)?;
+            write_csv_columns(out, hours, env.columns())?;
+        }
+        DataFormat::Json => write_json(out, env)?,
+    }
+    Ok(())
+}
+
+/// Writes every snapshot of the simulation
+fn write_snapshots(
+    out: &mut dyn Write,
+    simulation: &Simulation,
+    format: DataFormat,
+) -> Result<(), EnvError> {
+    match format {
+        DataFormat::Text => {
+            for snapshot in simulation.snapshots() {
+                let levels = snapshot
+                    .columns
+                    .iter()
+                    .map(|column| column.water_level().to_string())
+                    .collect::<Vec<String>>();
+                writeln!(out, "After {} hours: {}", snapshot.time, levels.join(" "))?;
+            }
+        }
+        DataFormat::Csv => {
+            writeln!(out, "{}", CSV_COLUMNS_HEADER)?;
+            for snapshot in simulation.snapshots() {
+                write_csv_columns(out, snapshot.time, &snapshot.columns)?;
+            }
+        }
+        DataFormat::Json => write_json(out, simulation.snapshots())?,
+    }
+    Ok(())
+}
+
+const CSV_COLUMNS_HEADER: &str = "time,pos,height,water_depth,water_level,water_volume";
+
+fn write_csv_columns(out: &mut dyn Write, time: f32, columns: &[Column]) -> Result<(), EnvError> {
+    for (pos, column) in columns.iter().enumerate() {
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            time,
+            pos + 1,
+            column.height(),
+            column.water(),
+            column.water_level(),
+            column.volume()
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the basins of water standing on the relief
+fn write_basins(
+    out: &mut dyn Write,
+    env: &Environment,
+    format: DataFormat,
+) -> Result<(), EnvError> {
+    let basins = env.basins();
+    match format {
+        DataFormat::Text => {
+            if basins.is_empty() {
+                writeln!(out, "No basins")?;
+            }
+            for (i, basin) in basins.iter().enumerate() {
+                writeln!(
+                    out,
+                    "Basin {}: columns {} to {}, level {}, volume {}, max depth {}, spills over {} at {}",
+                    i + 1,
+                    basin.start,
+                    basin.end,
+                    basin.level,
+                    basin.volume,
+                    basin.max_depth,
+                    basin.spill.pos,
+                    basin.spill.level
+                )?;
+            }
+        }
+        DataFormat::Csv => {
+            writeln!(
+                out,
+                "start,end,level,volume,max_depth,spill_pos,spill_level"
+            )?;
+            for basin in basins {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    basin.start,
+                    basin.end,
+                    basin.level,
+                    basin.volume,
+                    basin.max_depth,
+                    basin.spill.pos,
+                    basin.spill.level
+                )?;
+            }
+        }
+        DataFormat::Json => write_json(out, &basins)?,
+    }
+    Ok(())
+}
+
+/// Writes `value` as pretty printed JSON
+fn write_json<T: Serialize + ?Sized>(out: &mut dyn Write, value: &T) -> Result<(), EnvError> {
+    serde_json::to_writer_pretty(&mut *out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Reads the next line of the input, failing if there is none
+fn next_line(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    what: &str,
+) -> Result<String, EnvError> {
+    match lines.next() {
+        Some(line) => Ok(line?),
+        None => Err(EnvError::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("the input ended before {}", what),
+        ))),
+    }
+}
 
-    let mut env = env::Environment::new(relief);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    println!("Thank You!");
-    println!("How many hours of rain will occour?");
-    let rain_input = iterator.next().unwrap().unwrap();
-    let rain: f32 = rain_input.parse::<f32>().unwrap();
+    /// Runs the binary with `args` on the relief of `heights`, writing the output to a temporary file
+    fn run_on_relief(heights: impl Iterator<Item = u32>, args: &[&str]) -> Result<(), EnvError> {
+        let directory = std::env::temp_dir().join(format!("water-level-{}", process::id()));
+        fs::create_dir_all(&directory)?;
+        let relief = directory.join("relief.txt");
+        let heights = heights
+            .map(|height| height.to_string())
+            .collect::<Vec<String>>();
+        fs::write(&relief, heights.join(" "))?;
 
-    env.rain(rain);
+        let mut arguments = vec![
+            "--relief".to_string(),
+            relief.display().to_string(),
+            "-q".to_string(),
+            "-o".to_string(),
+            directory.join("output").display().to_string(),
+        ];
+        arguments.extend(args.iter().map(|arg| arg.to_string()));
+        let result = run(&cli::parse(arguments)?);
+        fs::remove_dir_all(&directory)?;
+        result
+    }
 
-    println!("{}", env);
+    #[test]
+    fn test_large_relief_conserves_water() {
+        // Adding up the rain and the water of these columns one by one once lost more than the tolerance
+        run_on_relief(0..2000, &["--hours", "1", "--format", "csv"]).unwrap();
+        run_on_relief(
+            (0..2000).map(|i| i * 7919 % 50),
+            &["--hours", "0.3", "--format", "csv"],
+        )
+        .unwrap();
+    }
 }
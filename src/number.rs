@@ -0,0 +1,543 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+use serde::{Serialize, Serializer};
+
+/// Number type the `Environment` stores its heights and water in
+///
+/// The crate implements it for `f32`, the default, `f64` and the exact `Rational`.
+pub trait Number:
+    Copy
+    + PartialOrd
+    + fmt::Debug
+    + fmt::Display
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + AddAssign
+    + SubAssign
+    + Sum
+{
+    const ZERO: Self;
+    const ONE: Self;
+    /// Height of an infinite wall, larger than any column
+    const MAX: Self;
+    /// Height of an open side, lower than any column
+    const MIN: Self;
+    /// Water below this amount is too small to be placed
+    const EPSILON: Self;
+    /// Largest height of a column that can be stored exactly
+    const MAX_HEIGHT: u32;
+
+    fn from_u32(value: u32) -> Self;
+    fn from_usize(value: usize) -> Self;
+    fn from_f32(value: f32) -> Self;
+    fn to_f32(self) -> f32;
+    fn is_finite(self) -> bool;
+    fn abs(self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+}
+
+macro_rules! impl_float {
+    ($float:ident, $max_height:expr) => {
+        impl Number for $float {
+            const ZERO: Self = 0.;
+            const ONE: Self = 1.;
+            const MAX: Self = $float::MAX;
+            const MIN: Self = $float::MIN;
+            const EPSILON: Self = $float::EPSILON;
+            const MAX_HEIGHT: u32 = $max_height;
+
+            fn from_u32(value: u32) -> Self {
+                value as $float
+            }
+
+            fn from_usize(value: usize) -> Self {
+                value as $float
+            }
+
+            fn from_f32(value: f32) -> Self {
+                value as $float
+            }
+
+            fn to_f32(self) -> f32 {
+                self as f32
+            }
+
+            fn is_finite(self) -> bool {
+                $float::is_finite(self)
+            }
+
+            fn abs(self) -> Self {
+                $float::abs(self)
+            }
+
+            fn min(self, other: Self) -> Self {
+                $float::min(self, other)
+            }
+
+            fn max(self, other: Self) -> Self {
+                $float::max(self, other)
+            }
+        }
+    };
+}
+
+impl_float!(f32, 1 << f32::MANTISSA_DIGITS);
+impl_float!(f64, u32::MAX);
+
+/// Adds up `values`, carrying the rounding error of every addition along and adding it back at the end
+///
+/// A plain sum over thousands of columns loses far more than the rounding error of the total, which the mass
+/// balance would report as water that has gone missing.
+pub(crate) fn compensated_sum<T: Number>(values: impl IntoIterator<Item = T>) -> T {
+    let mut total = T::ZERO;
+    let mut error = T::ZERO;
+    for value in values {
+        let next = total + value;
+        error += if total.abs() >= value.abs() {
+            (total - next) + value
+        } else {
+            (value - next) + total
+        };
+        total = next;
+    }
+    // Infinite terms leave a NaN error, the plain sum is right there
+    if error.is_finite() {
+        total + error
+    } else {
+        total
+    }
+}
+
+/// An exact fraction of two integers
+///
+/// Every operation is exact, so the results do not depend on the order of the operations and are
+/// reproducible to the bit. The sides of the relief are represented by the positive and negative
+/// infinity. Operations whose result does not fit into an `i128` fraction, or is undefined like 0 / 0, give
+/// NaN like they do for floats, which the `Environment` reports as an error.
+#[derive(Copy, Clone, Debug)]
+pub struct Rational {
+    /// Numerator, carries the sign, 0 for NaN
+    num: i128,
+    /// Denominator, positive for finite numbers and 0 for the infinities and NaN
+    den: i128,
+}
+
+impl Rational {
+    pub const INFINITY: Rational = Rational { num: 1, den: 0 };
+    pub const NEG_INFINITY: Rational = Rational { num: -1, den: 0 };
+    pub const NAN: Rational = Rational { num: 0, den: 0 };
+
+    /// Constructs the fraction `num / den` in its lowest terms
+    ///
+    /// Returns NaN if `num` or `den` is `i128::MIN`, which has no positive counterpart. Panics if `den` is 0.
+    pub fn new(num: i128, den: i128) -> Self {
+        assert!(den != 0, "rational with denominator 0");
+        if num == i128::MIN || den == i128::MIN {
+            return Rational::NAN;
+        }
+        let divisor = gcd(num, den) * den.signum();
+        Self {
+            num: num / divisor,
+            den: den / divisor,
+        }
+    }
+
+    /// Returns whether the number is NaN
+    pub fn is_nan(self) -> bool {
+        self.num == 0 && self.den == 0
+    }
+
+    /// Returns the numerator of the fraction in its lowest terms
+    pub fn numer(self) -> i128 {
+        self.num
+    }
+
+    /// Returns the denominator of the fraction in its lowest terms, 0 for the infinities
+    pub fn denom(self) -> i128 {
+        self.den
+    }
+
+    /// Constructs the fraction of the results of checked operations, NaN if one of them has overflowed
+    fn checked(num: Option<i128>, den: Option<i128>) -> Self {
+        match (num, den) {
+            (Some(num), Some(den)) => Rational::new(num, den),
+            _ => Rational::NAN,
+        }
+    }
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.abs().max(1)
+}
+
+/// Converts the integer, `i128::MIN` becomes NaN like in `Rational::new`
+impl From<i128> for Rational {
+    fn from(value: i128) -> Self {
+        Rational::new(value, 1)
+    }
+}
+
+impl Add for Rational {
+    type Output = Rational;
+
+    fn add(self, rhs: Rational) -> Rational {
+        match (self.den, rhs.den) {
+            // The sum of opposite infinities is undefined, like anything with NaN
+            (0, 0) if self.num != rhs.num => Rational::NAN,
+            (0, _) => self,
+            (_, 0) => rhs,
+            _ => {
+                // Dividing by the common factor first keeps the intermediate values small
+                let divisor = gcd(self.den, rhs.den);
+                let left = self.num.checked_mul(rhs.den / divisor);
+                let right = rhs.num.checked_mul(self.den / divisor);
+                Self::checked(
+                    left.zip(right)
+                        .and_then(|(left, right)| left.checked_add(right)),
+                    (self.den / divisor).checked_mul(rhs.den),
+                )
+            }
+        }
+    }
+}
+
+impl Neg for Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {
+            num: -self.num,
+            den: self.den,
+        }
+    }
+}
+
+impl Sub for Rational {
+    type Output = Rational;
+
+    fn sub(self, rhs: Rational) -> Rational {
+        self + -rhs
+    }
+}
+
+impl Mul for Rational {
+    type Output = Rational;
+
+    fn mul(self, rhs: Rational) -> Rational {
+        if self.den == 0 || rhs.den == 0 {
+            // The product of infinity and 0 is NaN, and so is anything with NaN
+            let sign = self.num.signum() * rhs.num.signum();
+            return Rational { num: sign, den: 0 };
+        }
+        // Cross cancel first so the products stay small
+        let first = gcd(self.num, rhs.den);
+        let second = gcd(rhs.num, self.den);
+        Self::checked(
+            (self.num / first).checked_mul(rhs.num / second),
+            (self.den / second).checked_mul(rhs.den / first),
+        )
+    }
+}
+
+impl Div for Rational {
+    type Output = Rational;
+
+    fn div(self, rhs: Rational) -> Rational {
+        match (rhs.num, rhs.den) {
+            // Dividing by 0 gives the infinity of the sign of the dividend, or NaN for 0 / 0
+            (0, 1) => Rational {
+                num: self.num.signum(),
+                den: 0,
+            },
+            // Dividing by infinity gives 0, unless the dividend is infinite or NaN as well
+            (_, 0) if self.den != 0 && rhs.num != 0 => Rational::ZERO,
+            (_, 0) => Rational::NAN,
+            (num, den) => self * Rational::new(den, num),
+        }
+    }
+}
+
+impl AddAssign for Rational {
+    fn add_assign(&mut self, rhs: Rational) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Rational {
+    fn sub_assign(&mut self, rhs: Rational) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sum for Rational {
+    fn sum<I: Iterator<Item = Rational>>(iter: I) -> Rational {
+        iter.fold(Rational::ZERO, Add::add)
+    }
+}
+
+/// NaN is not equal to anything, not even to itself
+impl PartialEq for Rational {
+    fn eq(&self, other: &Rational) -> bool {
+        !self.is_nan() && self.num == other.num && self.den == other.den
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        if self.is_nan() || other.is_nan() {
+            return None;
+        }
+        Some(match (self.den, other.den) {
+            (0, 0) => self.num.cmp(&other.num),
+            (0, _) => self.num.cmp(&0),
+            (_, 0) => 0.cmp(&other.num),
+            _ => compare(self.num, self.den, other.num, other.den),
+        })
+    }
+}
+
+/// Compares the fractions `a / b` and `c / d` with positive denominators, without overflowing
+///
+/// Compares the integer parts first and then the reciprocals of the remainders, like the continued fractions
+/// of both.
+fn compare(a: i128, b: i128, c: i128, d: i128) -> Ordering {
+    let (whole, other_whole) = (a.div_euclid(b), c.div_euclid(d));
+    if whole != other_whole {
+        return whole.cmp(&other_whole);
+    }
+    match (a.rem_euclid(b), c.rem_euclid(d)) {
+        (0, 0) => Ordering::Equal,
+        (0, _) => Ordering::Less,
+        (_, 0) => Ordering::Greater,
+        // The larger remainder has the smaller reciprocal
+        (rest, other_rest) => compare(d, other_rest, b, rest),
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.num, self.den) {
+            (0, 0) => f.write_str("NaN"),
+            (num, 0) if num > 0 => f.write_str("inf"),
+            (_, 0) => f.write_str("-inf"),
+            (num, 1) => write!(f, "{}", num),
+            (num, den) => write!(f, "{}/{}", num, den),
+        }
+    }
+}
+
+/// Serializes the fraction as a string like `"7/4"`, JSON numbers cannot hold it exactly
+impl Serialize for Rational {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Number for Rational {
+    const ZERO: Self = Rational { num: 0, den: 1 };
+    const ONE: Self = Rational { num: 1, den: 1 };
+    const MAX: Self = Rational::INFINITY;
+    const MIN: Self = Rational::NEG_INFINITY;
+    const EPSILON: Self = Rational::ZERO;
+    const MAX_HEIGHT: u32 = u32::MAX;
+
+    fn from_u32(value: u32) -> Self {
+        Rational::from(value as i128)
+    }
+
+    fn from_usize(value: usize) -> Self {
+        Rational::from(value as i128)
+    }
+
+    /// Converts the float exactly, every finite float is a fraction with a power of 2 as denominator
+    ///
+    /// The denominator of an `i128` fraction holds at most 2^126, so the floats below 2^-126 that need a larger
+    /// one are rounded to the nearest multiple of 2^-126. The floats of 2^127 and above do not fit the numerator
+    /// and become the infinities, like NaN and the infinities become themselves.
+    fn from_f32(value: f32) -> Self {
+        if value.is_nan() {
+            return Rational::NAN;
+        }
+        if value.is_infinite() {
+            return if value > 0. {
+                Rational::INFINITY
+            } else {
+                Rational::NEG_INFINITY
+            };
+        }
+
+        let bits = value.to_bits();
+        let sign = if bits >> 31 == 0 { 1 } else { -1 };
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = (bits & 0x7f_ffff) as i128;
+        let (mantissa, exponent) = if exponent == 0 {
+            (mantissa, -149)
+        } else {
+            (mantissa | 0x80_0000, exponent - 150)
+        };
+
+        if exponent >= 0 {
+            return match mantissa.checked_mul(1 << exponent) {
+                Some(num) => Rational::from(sign * num),
+                None => Rational::from(sign) / Rational::ZERO,
+            };
+        }
+
+        // Cancel the factors of 2 the mantissa has in common with the denominator
+        let shift = (mantissa.trailing_zeros() as i32).min(-exponent);
+        let (mantissa, exponent) = (mantissa >> shift, exponent + shift);
+        if exponent >= -126 {
+            Rational::new(sign * mantissa, 1 << -exponent)
+        } else {
+            let drop = -exponent - 126;
+            let rounded = (mantissa + (1 << (drop - 1))) >> drop;
+            Rational::new(sign * rounded, 1 << 126)
+        }
+    }
+
+    fn to_f32(self) -> f32 {
+        if self.den == 0 {
+            return match self.num {
+                0 => f32::NAN,
+                num if num > 0 => f32::INFINITY,
+                _ => f32::NEG_INFINITY,
+            };
+        }
+        (self.num as f64 / self.den as f64) as f32
+    }
+
+    fn is_finite(self) -> bool {
+        self.den != 0
+    }
+
+    fn abs(self) -> Self {
+        Rational {
+            num: self.num.abs(),
+            den: self.den,
+        }
+    }
+
+    /// Returns the smaller number, or the other one if one of them is NaN like `f32::min`
+    fn min(self, other: Self) -> Self {
+        if self.is_nan() || other < self {
+            other
+        } else {
+            self
+        }
+    }
+
+    /// Returns the larger number, or the other one if one of them is NaN like `f32::max`
+    fn max(self, other: Self) -> Self {
+        if self.is_nan() || other > self {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let third = Rational::new(1, 3);
+        let half = Rational::new(-2, -4);
+
+        assert_eq!(third + half, Rational::new(5, 6));
+        assert_eq!(third - half, Rational::new(-1, 6));
+        assert_eq!(third * half, Rational::new(1, 6));
+        assert_eq!(third / half, Rational::new(2, 3));
+        assert_eq!(third + third + third, Rational::ONE);
+        assert_eq!((third - half).to_string(), "-1/6");
+    }
+
+    #[test]
+    fn test_compensated_sum() {
+        // The exact sum of these rounded tenths is 900.0000358, which rounds to the `f32` 900.00006
+        let tenths = vec![0.3f32; 3000];
+        let exact = (0.3f32 as f64 * 3000.) as f32;
+        assert_eq!(compensated_sum(tenths.iter().copied()), exact);
+        assert!((tenths.iter().sum::<f32>() - exact).abs() > 1e-2);
+
+        assert_eq!(compensated_sum([1., f32::INFINITY, 2.]), f32::INFINITY);
+        assert_eq!(
+            compensated_sum([Rational::new(1, 3), Rational::new(1, 6)]),
+            Rational::new(1, 2)
+        );
+    }
+
+    #[test]
+    fn test_infinity() {
+        let two = Rational::from(2);
+
+        assert!(Rational::INFINITY > two);
+        assert!(Rational::NEG_INFINITY < -two);
+        assert_eq!(Rational::INFINITY - two, Rational::INFINITY);
+        assert_eq!(two - Rational::INFINITY, Rational::NEG_INFINITY);
+        assert_eq!(Number::min(Rational::INFINITY, two), two);
+    }
+
+    #[test]
+    fn test_from_f32() {
+        assert_eq!(Rational::from_f32(0.75), Rational::new(3, 4));
+        assert_eq!(Rational::from_f32(-3.), Rational::from(-3));
+        assert_eq!(Rational::from_f32(0.1).to_f32(), 0.1);
+        assert_eq!(Rational::from_f32(f32::INFINITY), Rational::INFINITY);
+        assert_eq!(Rational::from_f32(1e38).to_f32(), 1e38);
+        assert_eq!(Rational::from_f32(f32::MAX), Rational::INFINITY);
+        assert_eq!(
+            Rational::from_f32(f32::MIN_POSITIVE),
+            Rational::new(1, 1 << 126)
+        );
+        assert_eq!(
+            Rational::from_f32(f32::MIN_POSITIVE * 3.),
+            Rational::new(3, 1 << 126)
+        );
+        assert_eq!(Rational::from_f32(1e-45), Rational::ZERO);
+        assert!(Rational::from_f32(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_overflow() {
+        let big = Rational::from(i128::MAX / 2);
+        assert!((big + big + big).is_nan());
+        assert!((big * big).is_nan());
+        assert!(Rational::new(1, i128::MAX / 2) > Rational::new(1, i128::MAX));
+
+        // Comparing does not overflow, even where the difference would
+        assert!(
+            Rational::new(i128::MAX, i128::MAX - 1) < Rational::new(i128::MAX - 1, i128::MAX - 2)
+        );
+        assert!(Rational::new(-i128::MAX, 3) < Rational::new(i128::MAX, 2));
+    }
+
+    #[test]
+    fn test_nan() {
+        let two = Rational::from(2);
+
+        assert!((Rational::INFINITY - Rational::INFINITY).is_nan());
+        assert!((Rational::INFINITY * Rational::ZERO).is_nan());
+        assert!((Rational::ZERO / Rational::ZERO).is_nan());
+        assert!((Rational::INFINITY / Rational::INFINITY).is_nan());
+        assert_eq!(two / Rational::ZERO, Rational::INFINITY);
+        assert_eq!(two / Rational::NEG_INFINITY, Rational::ZERO);
+        assert!((Rational::NAN + two).is_nan());
+        assert!(Rational::NAN != Rational::NAN);
+        assert!(Rational::NAN.partial_cmp(&two).is_none());
+        assert_eq!(Number::min(Rational::NAN, two), two);
+        assert_eq!(Number::max(two, Rational::NAN), two);
+        assert!(Rational::NAN.to_f32().is_nan());
+        assert_eq!(Rational::NAN.to_string(), "NaN");
+    }
+}
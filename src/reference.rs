@@ -0,0 +1,404 @@
+//! A slow but simple reference simulator to test the flow algorithm against
+//!
+//! The reference follows the rain through time. At any moment the rain of every column runs down the water
+//! surface into the pool below it or over a side, so the pools rise at a steady rate until the first of them
+//! fills up to its rim. The reference then reroutes the rain over the new surface and goes on until the hour is
+//! over. There are no backwater, skip lists or stacks, and the water is stored as `Rational`, which keeps the
+//! levels exact.
+//!
+//! Water that reaches a flat stretch of the surface runs off over its lower ends. If both are lower, every full
+//! pool on the stretch passes the water that reaches it evenly over both of its rims, and water that runs over a
+//! rim runs on down its far side, so where it leaves depends on the pools it passes on the way. The flow algorithm
+//! spills the rows of full pools by the same rule, so the two are compared exactly.
+//!
+//! The tests generate random reliefs and rains with `proptest`, which shrinks a failing case down to a
+//! minimal relief before reporting it.
+
+use crate::boundary::Boundary;
+use crate::number::{Number, Rational};
+
+/// The relief and the water standing on it, as seen by the reference simulator
+pub(crate) struct Reference {
+    /// Heights of the columns, with the left and the right side at both ends
+    heights: Vec<Rational>,
+    water: Vec<Rational>,
+    /// Units of water that have left the relief over the left and the right side
+    outflow: (Rational, Rational),
+}
+
+/// Where the rain runs to while no pool fills up, per hour
+struct Routes {
+    /// The first and the last position of every pool that the rain reaches, with the water it receives
+    pools: Vec<(usize, usize, Rational)>,
+    outflow: (Rational, Rational),
+}
+
+impl Reference {
+    /// Constructs a dry relief between the `left` and the `right` side, each a wall or open
+    pub(crate) fn new(relief: &[u32], left: Boundary, right: Boundary) -> Self {
+        let mut heights = vec![left.height()];
+        heights.extend(relief.iter().map(|height| Rational::from(*height as i128)));
+        heights.push(right.height());
+
+        Self {
+            water: vec![Rational::from(0); heights.len()],
+            heights,
+            outflow: (Rational::from(0), Rational::from(0)),
+        }
+    }
+
+    /// Returns the height of the water surface in position `pos`, the positions are the same as in `Environment`
+    pub(crate) fn water_level(&self, pos: usize) -> Rational {
+        self.heights[pos] + self.water[pos]
+    }
+
+    /// Returns the units of water that have left the relief over the left and the right side
+    pub(crate) fn outflow(&self) -> (Rational, Rational) {
+        self.outflow
+    }
+
+    /// Returns the units of water standing on the relief
+    pub(crate) fn stored(&self) -> Rational {
+        self.water.iter().copied().sum()
+    }
+
+    /// Rains for `hours` hours on every column
+    pub(crate) fn rain(&mut self, hours: Rational) {
+        self.rain_profile(&vec![hours; self.heights.len() - 2]);
+    }
+
+    /// Rains `profile[pos - 1]` units of water on the column in position `pos`, evenly over one hour
+    pub(crate) fn rain_profile(&mut self, profile: &[Rational]) {
+        let mut remaining = Rational::from(1);
+        while remaining > Rational::from(0) {
+            let mut routes = Routes {
+                pools: Vec::new(),
+                outflow: (Rational::from(0), Rational::from(0)),
+            };
+            for (pos, rain) in (1..self.heights.len() - 1).zip(profile.iter().copied()) {
+                self.route(pos, rain, &mut routes);
+            }
+
+            // The rain keeps its course until the first pool is full
+            let mut hours = remaining;
+            for (start, end, water) in &routes.pools {
+                let rim = self.water_level(start - 1).min(self.water_level(end + 1));
+                let width = Rational::from((end - start + 1) as i128);
+                hours = hours.min((rim - self.water_level(*start)) * width / *water);
+            }
+
+            for (start, end, water) in &routes.pools {
+                let rise = *water * hours / Rational::from((end - start + 1) as i128);
+                for water in &mut self.water[*start..=*end] {
+                    *water += rise;
+                }
+            }
+            self.outflow.0 += routes.outflow.0 * hours;
+            self.outflow.1 += routes.outflow.1 * hours;
+            remaining -= hours;
+        }
+    }
+
+    /// Routes `water` units of water per hour from the column in position `pos` down the surface
+    fn route(&self, pos: usize, water: Rational, routes: &mut Routes) {
+        let last = self.heights.len() - 1;
+        if water == Rational::from(0) {
+            return;
+        }
+        if pos == 0 {
+            routes.outflow.0 += water;
+            return;
+        }
+        if pos == last {
+            routes.outflow.1 += water;
+            return;
+        }
+
+        // The water spreads over the whole flat stretch of the surface it has reached
+        let level = self.water_level(pos);
+        let mut start = pos;
+        while start > 1 && self.water_level(start - 1) == level {
+            start -= 1;
+        }
+        let mut end = pos;
+        while end < last - 1 && self.water_level(end + 1) == level {
+            end += 1;
+        }
+
+        // Water leaves over a side that is as high as the surface
+        let left =
+            self.water_level(start - 1) < level || start == 1 && self.water_level(0) <= level;
+        let right =
+            self.water_level(end + 1) < level || end == last - 1 && self.water_level(last) <= level;
+
+        match (left, right) {
+            (false, false) => match routes.pools.iter_mut().find(|pool| pool.0 == start) {
+                Some(pool) => pool.2 += water,
+                None => routes.pools.push((start, end, water)),
+            },
+            (true, true) => {
+                // The full pools on the stretch, counted from the left end. Of the water in the pool `k` of `n`,
+                // the share `(n + 1 - k) / (n + 1)` leaves over the left end, and water on a rim runs half into
+                // the pool on each side of it, the ends counting as pools that keep all of it.
+                let pools = (start..=end)
+                    .filter(|pos| {
+                        self.water[*pos] > Rational::from(0)
+                            && (*pos == start || self.water[pos - 1] == Rational::from(0))
+                    })
+                    .collect::<Vec<usize>>();
+                let n = pools.len() as i128;
+                let reach = |k: i128| Rational::new(n + 1 - k, n + 1);
+                let k = pools.iter().filter(|first| **first <= pos).count() as i128;
+                let share = if self.water[pos] > Rational::from(0) {
+                    reach(k)
+                } else {
+                    (reach(k) + reach(k + 1)) / Rational::from(2)
+                };
+                let left = water * share;
+                self.route(start - 1, left, routes);
+                self.route(end + 1, water - left, routes);
+            }
+            (true, false) => self.route(start - 1, water, routes),
+            (false, true) => self.route(end + 1, water, routes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Environment;
+    use crate::number::Number;
+    use proptest::prelude::*;
+    use proptest::test_runner::TestCaseError;
+
+    /// Reliefs of up to a dozen low columns, so that plateaus and rims of the same height are common
+    fn relief() -> impl Strategy<Value = Vec<u32>> {
+        prop::collection::vec(0..8u32, 1..12)
+    }
+
+    /// Hours of rain in eighths, which `f32` stores exactly
+    fn hours() -> impl Strategy<Value = Rational> {
+        (1..40i128).prop_map(|eighths| Rational::new(eighths, 8))
+    }
+
+    fn boundary() -> impl Strategy<Value = Boundary> {
+        prop_oneof![Just(Boundary::Wall), Just(Boundary::Open)]
+    }
+
+    fn environment<T: Number>(relief: &[u32], left: Boundary, right: Boundary) -> Environment<T> {
+        Environment::from_relief(relief.to_vec())
+            .unwrap()
+            .with_boundaries(left, right)
+            .unwrap()
+    }
+
+    /// Whether the `f32` result is the exact `expected` one, up to rounding
+    fn close(value: f32, expected: Rational) -> bool {
+        let expected = expected.to_f32();
+        (value - expected).abs() <= 1e-4 * expected.abs().max(1.)
+    }
+
+    /// Checks that the exact solver has placed the water exactly like the reference
+    fn compare(env: &Environment<Rational>, reference: &Reference) -> Result<(), TestCaseError> {
+        for pos in 1..=env.len() {
+            prop_assert_eq!(
+                env.water_level(pos),
+                reference.water_level(pos),
+                "position {}",
+                pos
+            );
+        }
+        prop_assert_eq!(env.outflow(), reference.outflow());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reference_valley_overflow() {
+        let mut reference = Reference::new(&[3, 1, 2], Boundary::Wall, Boundary::Wall);
+        reference.rain(Rational::new(1, 1));
+
+        assert_eq!(reference.water_level(1), Rational::new(3, 1));
+        assert_eq!(reference.water_level(2), Rational::new(3, 1));
+        assert_eq!(reference.water_level(3), Rational::new(3, 1));
+    }
+
+    #[test]
+    fn test_reference_peak() {
+        let mut reference = Reference::new(&[1, 4, 2], Boundary::Open, Boundary::Wall);
+        reference.rain(Rational::new(1, 1));
+
+        assert_eq!(reference.water_level(1), Rational::new(1, 1));
+        assert_eq!(reference.water_level(3), Rational::new(7, 2));
+        assert_eq!(
+            reference.outflow(),
+            (Rational::new(3, 2), Rational::from(0))
+        );
+    }
+
+    #[test]
+    fn test_reference_row_of_full_pools() {
+        // Both pools fill up at the same time, from then on all the rain runs evenly over both sides
+        let mut reference = Reference::new(&[6, 0, 6, 0, 6], Boundary::Open, Boundary::Open);
+        reference.rain(Rational::new(25, 8));
+
+        assert_eq!(reference.water_level(2), Rational::new(6, 1));
+        assert_eq!(reference.water_level(4), Rational::new(6, 1));
+        assert_eq!(
+            reference.outflow(),
+            (Rational::new(29, 16), Rational::new(29, 16))
+        );
+    }
+
+    #[test]
+    fn test_reference_fill_order() {
+        // Until the pool fills up after 6/5 hours, the left plateau sends half of its rain into it and the right rim
+        // half of its rain. From then on the pool passes all it receives evenly over both rims, and the water over
+        // the left rim runs on over the plateau, so more of it leaves over the left side.
+        let mut reference = Reference::new(&[5, 5, 2, 5], Boundary::Open, Boundary::Open);
+        reference.rain(Rational::new(7, 4));
+        assert_eq!(
+            reference.outflow(),
+            (Rational::new(39, 16), Rational::new(25, 16))
+        );
+
+        let mut env = environment::<Rational>(&[5, 5, 2, 5], Boundary::Open, Boundary::Open);
+        env.rain(Rational::new(7, 4)).unwrap();
+        compare(&env, &reference).unwrap();
+    }
+
+    #[test]
+    fn test_equal_rims_regressions() {
+        // More rain once sent less water over a side of these reliefs, where pools fill up to rims of the same height
+        let reliefs = [
+            (vec![5, 2, 5, 4], Boundary::Open, Boundary::Open),
+            (vec![4, 1, 4, 0, 1, 1], Boundary::Open, Boundary::Wall),
+            (vec![5, 0, 3, 5, 3, 4, 4], Boundary::Open, Boundary::Open),
+            (vec![3, 5, 1, 5, 4, 5, 1], Boundary::Open, Boundary::Open),
+        ];
+
+        for (relief, left, right) in reliefs {
+            let mut outflow = (Rational::from(0), Rational::from(0));
+            let mut expected = (Rational::from(0), Rational::from(0));
+            for steps in 1..=320 {
+                let hours = Rational::new(steps, 32);
+                let mut reference = Reference::new(&relief, left, right);
+                reference.rain(hours);
+                let mut env = environment::<Rational>(&relief, left, right);
+                env.rain(hours).unwrap();
+
+                compare(&env, &reference).unwrap();
+                assert!(
+                    env.outflow().0 >= outflow.0 && env.outflow().1 >= outflow.1,
+                    "{:?} after {} hours",
+                    relief,
+                    hours
+                );
+                assert!(
+                    reference.outflow().0 >= expected.0 && reference.outflow().1 >= expected.1,
+                    "{:?} after {} hours",
+                    relief,
+                    hours
+                );
+                outflow = env.outflow();
+                expected = reference.outflow();
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_against_reference(
+            relief in relief(),
+            hours in hours(),
+            left in boundary(),
+            right in boundary(),
+        ) {
+            let mut reference = Reference::new(&relief, left, right);
+            reference.rain(hours);
+
+            let mut exact = environment::<Rational>(&relief, left, right);
+            exact.rain(hours).unwrap();
+            compare(&exact, &reference)?;
+
+            let mut env = environment::<f32>(&relief, left, right);
+            env.rain(hours.to_f32()).unwrap();
+
+            for pos in 1..=relief.len() {
+                prop_assert!(
+                    close(env.water_level(pos), exact.water_level(pos)),
+                    "water level {} in position {}, expected {}",
+                    env.water_level(pos),
+                    pos,
+                    exact.water_level(pos)
+                );
+            }
+            let (left, right) = env.outflow();
+            prop_assert!(close(left, exact.outflow().0));
+            prop_assert!(close(right, exact.outflow().1));
+        }
+
+        #[test]
+        fn test_profile_against_reference(
+            (relief, profile) in relief().prop_flat_map(|relief| {
+                let len = relief.len();
+                (Just(relief), prop::collection::vec(prop_oneof![Just(0i128), 1..24i128], len))
+            }),
+            left in boundary(),
+            right in boundary(),
+        ) {
+            let profile = profile.into_iter().map(|eighths| Rational::new(eighths, 8)).collect::<Vec<Rational>>();
+            let mut reference = Reference::new(&relief, left, right);
+            reference.rain_profile(&profile);
+
+            let mut exact = environment::<Rational>(&relief, left, right);
+            exact.rain_profile(&profile).unwrap();
+            compare(&exact, &reference)?;
+        }
+
+        #[test]
+        fn test_conservation(
+            relief in relief(),
+            hours in hours(),
+            left in boundary(),
+            right in boundary(),
+        ) {
+            let rain = hours * Rational::from(relief.len() as i128);
+
+            let mut reference = Reference::new(&relief, left, right);
+            reference.rain(hours);
+            let (outflow_left, outflow_right) = reference.outflow();
+            prop_assert_eq!(reference.stored() + outflow_left + outflow_right, rain);
+
+            let mut env = environment::<Rational>(&relief, left, right);
+            prop_assert_eq!(env.rain(hours).unwrap(), Rational::from(0));
+            let balance = env.mass_balance();
+            prop_assert_eq!(balance.rain_in, rain);
+            prop_assert_eq!(balance.residual(), Rational::from(0));
+
+            let mut env = environment::<f32>(&relief, left, right);
+            env.rain(hours.to_f32()).unwrap();
+            prop_assert!(env.mass_balance().residual().abs() <= 1e-4 * rain.to_f32());
+        }
+
+        #[test]
+        fn test_monotonic_in_hours(
+            relief in relief(),
+            hours in hours(),
+            more in hours(),
+            left in boundary(),
+            right in boundary(),
+        ) {
+            let mut env = environment::<Rational>(&relief, left, right);
+            env.rain(hours).unwrap();
+            let mut wetter = environment::<Rational>(&relief, left, right);
+            wetter.rain(hours + more).unwrap();
+
+            for pos in 1..=relief.len() {
+                prop_assert!(wetter.water_level(pos) >= env.water_level(pos), "position {}", pos);
+            }
+            prop_assert!(wetter.outflow().0 >= env.outflow().0);
+            prop_assert!(wetter.outflow().1 >= env.outflow().1);
+        }
+    }
+}
@@ -0,0 +1,541 @@
+//! Pictures of the relief and the water standing on it
+
+use std::fmt::Write;
+use std::io;
+
+use crate::env::{Column, Environment};
+use crate::error::EnvError;
+use crate::number::Number;
+
+/// Size of the area the relief is drawn in by `render_svg`, in pixels
+const PLOT_WIDTH: f32 = 640.;
+const PLOT_HEIGHT: f32 = 320.;
+
+/// Space around the plot for the axis labels, in pixels
+const MARGIN_LEFT: f32 = 56.;
+const MARGIN_RIGHT: f32 = 16.;
+const MARGIN_TOP: f32 = 16.;
+const MARGIN_BOTTOM: f32 = 40.;
+
+/// Most labels on each axis
+const MAX_TICKS: usize = 8;
+const MAX_COLUMN_LABELS: usize = 24;
+
+/// Most rows drawn by `render_ascii`
+const MAX_ASCII_ROWS: usize = 200;
+
+/// Blocks filling the lower 0 to 8 eighths of a character cell
+const EIGHTHS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Colour of the relief in the 256 colour palette of the terminal
+const EARTH: u8 = 94;
+
+/// Colours of the water in the 256 colour palette, from the shallowest to the deepest
+const WATER: [u8; 6] = [153, 117, 75, 33, 26, 19];
+
+/// Draws the relief with `O` and the water with `x`, one character for every unit of height
+///
+/// Every level is rounded down to a whole unit, starting at 0 or at the lowest column if it lies below 0. A relief
+/// taller than `MAX_ASCII_ROWS` units is drawn with as many whole units per row as it takes to fit.
+pub fn render_ascii<T: Number>(env: &Environment<T>) -> String {
+    let mut top = 0f64;
+    let mut bottom = 0f64;
+    for col in env.columns() {
+        top = top.max(col.water_level().to_f32().floor() as f64);
+        bottom = bottom.min(col.height().to_f32().floor() as f64);
+    }
+    let unit = ((top - bottom) / MAX_ASCII_ROWS as f64).ceil().max(1.);
+    let row = |value: T| ((value.to_f32().floor() as f64 - bottom) / unit).floor() as i64;
+    let rows = ((top - bottom) / unit).ceil() as i64;
+
+    let mut picture = String::new();
+    for level in (0..rows).rev() {
+        for col in env.columns() {
+            if row(col.height()) > level {
+                picture.push('O');
+            } else if row(col.water_level()) > level {
+                picture.push('x');
+            } else {
+                picture.push(' ');
+            }
+        }
+        picture.push('\n');
+    }
+    picture
+}
+
+/// Draws the relief and the water in colour for a terminal of `width` by `height` characters
+///
+/// The relief is drawn in brown and the water in blue, darker where it is deeper. The top of every column
+/// and of the water is drawn with eighth blocks, so levels are shown to an eighth of a row. A relief wider
+/// than `width` is drawn with the highest column of every group of neighbouring columns, a relief taller
+/// than `height` with more than one unit of height per row.
+pub fn render_terminal<T: Number>(env: &Environment<T>, width: usize, height: usize) -> String {
+    terminal_frame(env.columns(), width, height, extent(env.columns()))
+}
+
+/// Draws the `columns` like `render_terminal` with the rows spanning the levels from `bottom` to `top`
+///
+/// Drawing every frame of an animation with the same levels keeps the picture from jumping around.
+pub(crate) fn terminal_frame<T: Number>(
+    columns: &[Column<T>],
+    width: usize,
+    height: usize,
+    (bottom, top): (f32, f32),
+) -> String {
+    let cells = columns.len().min(width.max(1));
+    // The highest column and water level of the columns drawn in every cell
+    let groups = (0..cells)
+        .map(|cell| {
+            let group = &columns[cell * columns.len() / cells..(cell + 1) * columns.len() / cells];
+            group.iter().fold(
+                (f32::MIN, f32::MIN, 0f32),
+                |(ground, level, depth), column| {
+                    (
+                        ground.max(column.height().to_f32()),
+                        level.max(column.water_level().to_f32()),
+                        depth.max(column.water().to_f32()),
+                    )
+                },
+            )
+        })
+        .collect::<Vec<(f32, f32, f32)>>();
+
+    let (bottom, top) = (bottom.floor(), top.ceil());
+    let rows = height.max(1);
+    let unit = ((top - bottom) / rows as f32).max(1.);
+    let rows = ((top - bottom) / unit).ceil() as usize;
+    let deepest = groups.iter().map(|group| group.2).fold(0., f32::max);
+
+    let mut picture = String::new();
+    for row in (0..rows).rev() {
+        let floor = bottom + row as f32 * unit;
+        let eighths = |level: f32| (((level - floor) / unit).clamp(0., 1.) * 8.).round() as usize;
+        let mut style = (None, None);
+        for &(ground, level, depth) in &groups {
+            let water = WATER[((depth / deepest) * (WATER.len() - 1) as f32).ceil() as usize];
+            let (earth, wet) = (eighths(ground), eighths(level));
+            let (glyph, cell) = match (earth, wet) {
+                (8, _) => (' ', (None, Some(EARTH))),
+                (0, 8) => (' ', (None, Some(water))),
+                (0, 0) => (' ', (None, None)),
+                (0, wet) => (EIGHTHS[wet], (Some(water), None)),
+                // The water above a partly filled column colours the cell once it covers half of it
+                (earth, wet) => (
+                    EIGHTHS[earth],
+                    (Some(EARTH), Some(water).filter(|_| wet > earth && wet >= 4)),
+                ),
+            };
+            if cell != style {
+                picture.push_str("\x1b[0m");
+                if let Some(fg) = cell.0 {
+                    let _ = write!(picture, "\x1b[38;5;{}m", fg);
+                }
+                if let Some(bg) = cell.1 {
+                    let _ = write!(picture, "\x1b[48;5;{}m", bg);
+                }
+                style = cell;
+            }
+            picture.push(glyph);
+        }
+        picture.push_str("\x1b[0m\n");
+    }
+    picture
+}
+
+/// Draws the relief and the water as an image of `width` by `height` pixels
+///
+/// Returns 3 bytes of red, green and blue for every pixel, row by row from the top left corner. The relief
+/// is scaled to fill the image and the water is shaded like in `render_terminal`.
+pub fn render_pixels<T: Number>(env: &Environment<T>, width: u32, height: u32) -> Vec<u8> {
+    pixels_frame(env.columns(), width, height, extent(env.columns()))
+}
+
+/// Draws the `columns` like `render_pixels` with the image spanning the levels from `bottom` to `top`
+pub(crate) fn pixels_frame<T: Number>(
+    columns: &[Column<T>],
+    width: u32,
+    height: u32,
+    (bottom, top): (f32, f32),
+) -> Vec<u8> {
+    let mut edges = vec![0.];
+    for column in columns {
+        edges.push(edges[edges.len() - 1] + column.width().to_f32());
+    }
+    let total = edges[edges.len() - 1];
+    let top = if top > bottom { top } else { bottom + 1. };
+    let deepest = columns
+        .iter()
+        .map(|column| column.water().to_f32())
+        .fold(0., f32::max);
+
+    // Every column of pixels shows the column of the relief under its center
+    let shown = (0..width)
+        .map(|x| {
+            let offset = (x as f32 + 0.5) / width as f32 * total;
+            let i = edges[1..].partition_point(|edge| *edge <= offset);
+            let column = columns[i.min(columns.len() - 1)];
+            let water = column.water().to_f32() / deepest;
+            let shade = |light: u8, dark: u8| {
+                (light as f32 + (dark as f32 - light as f32) * water).round() as u8
+            };
+            (
+                column.height().to_f32(),
+                column.water_level().to_f32(),
+                [shade(166, 20), shade(206, 60), shade(240, 140)],
+            )
+        })
+        .collect::<Vec<(f32, f32, [u8; 3])>>();
+
+    let mut image = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        let level = top - (y as f32 + 0.5) / height as f32 * (top - bottom);
+        for (ground, surface, water) in &shown {
+            if level < *ground {
+                image.extend_from_slice(&[139, 107, 74]);
+            } else if level < *surface {
+                image.extend_from_slice(water);
+            } else {
+                image.extend_from_slice(&[255, 255, 255]);
+            }
+        }
+    }
+    image
+}
+
+/// Draws the relief and the water like `render_pixels` and encodes the image as PNG
+pub fn render_png<T: Number>(
+    env: &Environment<T>,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, EnvError> {
+    encode_png(&render_pixels(env, width, height), width, height)
+}
+
+/// Encodes an image of 3 bytes per pixel as PNG
+pub(crate) fn encode_png(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, EnvError> {
+    let mut encoded = vec![];
+    let mut encoder = png::Encoder::new(&mut encoded, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::from)?;
+    writer.write_image_data(pixels).map_err(io::Error::from)?;
+    writer.finish().map_err(io::Error::from)?;
+    Ok(encoded)
+}
+
+/// Returns the lowest and the highest level of the `columns`, taking 0 as the ground
+pub(crate) fn extent<T: Number>(columns: &[Column<T>]) -> (f32, f32) {
+    columns.iter().fold((0., 0.), |(bottom, top), column| {
+        (
+            f32::min(bottom, column.height().to_f32()),
+            f32::max(top, column.water_level().to_f32()),
+        )
+    })
+}
+
+/// Draws the relief and the water as an SVG document
+///
+/// The picture is always the same size, the relief is scaled to fit. The water is drawn up to its exact
+/// level, every basin is labelled with its level and volume, the vertical axis with the heights and the
+/// horizontal axis with the positions of the columns.
+pub fn render_svg<T: Number>(env: &Environment<T>) -> String {
+    let columns = env.columns();
+    let mut edges = vec![0.];
+    for column in columns {
+        edges.push(edges[edges.len() - 1] + column.width().to_f32());
+    }
+    let mut bottom = columns
+        .iter()
+        .map(|column| column.height().to_f32())
+        .fold(0., f32::min);
+    let mut top = columns
+        .iter()
+        .map(|column| column.water_level().to_f32())
+        .fold(0., f32::max);
+    if top <= bottom {
+        top += 1.;
+    }
+    // Leave some room for the labels of the basins and for the ground below the lowest column
+    let headroom = (top - bottom) * 0.15;
+    top += headroom;
+    if bottom < 0. {
+        bottom -= headroom;
+    }
+
+    let x = |offset: f32| MARGIN_LEFT + offset / edges[edges.len() - 1] * PLOT_WIDTH;
+    let y = |level: f32| MARGIN_TOP + (top - level) / (top - bottom) * PLOT_HEIGHT;
+
+    let mut picture = String::new();
+    let width = MARGIN_LEFT + PLOT_WIDTH + MARGIN_RIGHT;
+    let height = MARGIN_TOP + PLOT_HEIGHT + MARGIN_BOTTOM;
+    let _ = writeln!(
+        picture,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}" font-family="sans-serif" font-size="11">"#,
+        width, height
+    );
+
+    // The relief as one outline, from the bottom left corner over every column to the bottom right corner
+    let _ = write!(
+        picture,
+        r##"<path class="terrain" fill="#8b6b4a" d="M{} {}"##,
+        x(0.),
+        y(bottom)
+    );
+    for (i, column) in columns.iter().enumerate() {
+        let level = y(column.height().to_f32());
+        let _ = write!(
+            picture,
+            " L{} {} L{} {}",
+            x(edges[i]),
+            level,
+            x(edges[i + 1]),
+            level
+        );
+    }
+    let _ = writeln!(
+        picture,
+        r#" L{} {} Z"/>"#,
+        x(edges[columns.len()]),
+        y(bottom)
+    );
+
+    // Every basin from its flat surface down along the columns below it
+    for basin in env.basins() {
+        let level = basin.level.to_f32();
+        let (start, end) = (basin.start - 1, basin.end - 1);
+        let _ = write!(
+            picture,
+            r##"<path class="water" fill="#4a90d9" fill-opacity="0.8" d="M{} {} L{} {}"##,
+            x(edges[start]),
+            y(level),
+            x(edges[end + 1]),
+            y(level)
+        );
+        for i in (start..=end).rev() {
+            let floor = y(columns[i].height().to_f32());
+            let _ = write!(
+                picture,
+                " L{} {} L{} {}",
+                x(edges[i + 1]),
+                floor,
+                x(edges[i]),
+                floor
+            );
+        }
+        let _ = writeln!(picture, r#" Z"/>"#);
+        let _ = writeln!(
+            picture,
+            r#"<text class="basin" x="{}" y="{}" text-anchor="middle">level {}, volume {}</text>"#,
+            x((edges[start] + edges[end + 1]) / 2.),
+            y(level) - 4.,
+            label(level),
+            label(basin.volume.to_f32())
+        );
+    }
+
+    // The axes with the heights on the left and the positions of the columns at the bottom
+    let _ = writeln!(
+        picture,
+        r#"<path class="axis" fill="none" stroke="black" d="M{0} {1} L{0} {2} L{3} {2}"/>"#,
+        x(0.),
+        MARGIN_TOP,
+        MARGIN_TOP + PLOT_HEIGHT,
+        MARGIN_LEFT + PLOT_WIDTH
+    );
+    let step = tick_step((top - bottom) / MAX_TICKS as f32);
+    let mut tick = (bottom / step).ceil() * step;
+    while tick <= top {
+        let _ = writeln!(
+            picture,
+            r#"<path stroke="black" d="M{0} {1} L{2} {1}"/><text x="{3}" y="{1}" text-anchor="end" dominant-baseline="middle">{4}</text>"#,
+            x(0.) - 4.,
+            y(tick),
+            x(0.),
+            x(0.) - 6.,
+            label(tick)
+        );
+        tick += step;
+    }
+    let every = columns.len().div_ceil(MAX_COLUMN_LABELS);
+    for i in (0..columns.len()).step_by(every) {
+        let _ = writeln!(
+            picture,
+            r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+            x((edges[i] + edges[i + 1]) / 2.),
+            MARGIN_TOP + PLOT_HEIGHT + 16.,
+            i + 1
+        );
+    }
+
+    picture.push_str("</svg>\n");
+    picture
+}
+
+/// Returns the step of 1, 2 or 5 times a power of 10 which is at least `step`
+fn tick_step(step: f32) -> f32 {
+    let magnitude = 10f32.powf(step.log10().floor());
+    [1., 2., 5., 10.]
+        .iter()
+        .map(|factor| factor * magnitude)
+        .find(|nice| *nice >= step)
+        .unwrap_or(10. * magnitude)
+}
+
+/// Formats a number for a label, with at most 2 decimals
+fn label(value: f32) -> String {
+    let label = format!("{:.2}", value);
+    let label = label.trim_end_matches('0').trim_end_matches('.');
+    match label {
+        "-0" => "0".to_string(),
+        label => label.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq as approx_eq;
+
+    #[test]
+    fn test_ascii() {
+        let mut env = Environment::new(vec![3, 1, 2]).unwrap();
+        env.rain(1.0).unwrap();
+
+        assert_eq!(render_ascii(&env), "Oxx\nOxO\nOOO\n");
+    }
+
+    #[test]
+    fn test_ascii_below_zero() {
+        let env = Environment::from_heights(vec![1f32, -1.5]).unwrap();
+
+        assert_eq!(render_ascii(&env), "O \nO \nO \n");
+    }
+
+    #[test]
+    fn test_ascii_scaled() {
+        // A billion units are drawn with 5 million units per row
+        let mut env = Environment::from_heights(vec![1e9f64, 0., 5e8]).unwrap();
+        env.rain(2e6).unwrap();
+
+        let picture = render_ascii(&env);
+        assert_eq!(picture.lines().count(), MAX_ASCII_ROWS);
+        assert_eq!(picture.lines().next(), Some("O  "));
+        assert_eq!(picture.lines().last(), Some("OxO"));
+    }
+
+    #[test]
+    fn test_svg() {
+        let mut env = Environment::new(vec![4, 0, 4]).unwrap();
+        env.rain(0.5).unwrap();
+
+        let picture = render_svg(&env);
+        assert!(picture.starts_with("<svg "));
+        assert!(picture.ends_with("</svg>\n"));
+        assert_eq!(picture.matches(r#"class="terrain""#).count(), 1);
+        assert_eq!(picture.matches(r#"class="water""#).count(), 1);
+        assert!(picture.contains("level 1.5, volume 1.5</text>"));
+        assert!(picture.contains(">2</text>"));
+    }
+
+    #[test]
+    fn test_svg_fractional_water() {
+        let mut env = Environment::new(vec![4, 0, 4]).unwrap();
+        env.rain(0.5).unwrap();
+        let water = render_svg(&env);
+        let water = water
+            .lines()
+            .find(|line| line.contains(r#"class="water""#))
+            .unwrap();
+
+        // The picture goes from 0 up to 4 plus 15% of headroom
+        let surface = MARGIN_TOP + (4.6 - 1.5) / 4.6 * PLOT_HEIGHT;
+        assert!(water.contains(&format!(" {} L", surface)));
+    }
+
+    #[test]
+    fn test_svg_size() {
+        let low = render_svg(&Environment::new(vec![1, 0, 1]).unwrap());
+        let high = render_svg(&Environment::new(vec![10000, 0, 250]).unwrap());
+
+        assert_eq!(low.lines().next(), high.lines().next());
+        assert!(high.contains(">10000</text>"));
+    }
+
+    #[test]
+    fn test_tick_step() {
+        approx_eq!(tick_step(0.3), 0.5);
+        approx_eq!(tick_step(1.), 1.);
+        approx_eq!(tick_step(140.), 200.);
+        assert_eq!(label(2.5), "2.5");
+        assert_eq!(label(1. / 3.), "0.33");
+        assert_eq!(label(-0.001), "0");
+    }
+
+    /// Removes the colours, leaving the characters of the picture
+    fn plain(picture: &str) -> String {
+        let mut plain = String::new();
+        let mut escape = false;
+        for c in picture.chars() {
+            match c {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                _ if escape => {}
+                c => plain.push(c),
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn test_terminal() {
+        let mut env = Environment::new(vec![2, 0, 1]).unwrap();
+        env.rain(0.5).unwrap();
+
+        // The water stands 1.25 deep on the second column and 0.25 on the third
+        assert_eq!(
+            render_terminal(&env, 80, 24),
+            "\x1b[0m\x1b[48;5;94m \x1b[0m\x1b[38;5;19m▂\x1b[0m\x1b[38;5;117m▂\x1b[0m\n\
+             \x1b[0m\x1b[48;5;94m \x1b[0m\x1b[48;5;19m \x1b[0m\x1b[48;5;94m \x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_terminal_fractional() {
+        let mut env = Environment::new(vec![1, 0]).unwrap();
+        env.rain(0.25).unwrap();
+
+        assert_eq!(plain(&render_terminal(&env, 80, 24)), " ▄\n");
+        let mut env = Environment::from_heights(vec![2.5f32, 0.]).unwrap();
+        env.rain(0.1).unwrap();
+        let picture = render_terminal(&env, 80, 24);
+        assert_eq!(plain(&picture), "▄ \n  \n ▂\n");
+        assert!(picture.starts_with("\x1b[0m\x1b[38;5;94m▄\x1b[0m "));
+    }
+
+    #[test]
+    fn test_terminal_scaled() {
+        let relief = (0..200).map(|i| (i * 37 % 1000) as u32).collect();
+        let mut env = Environment::new(relief).unwrap();
+        env.rain(10.).unwrap();
+
+        let picture = plain(&render_terminal(&env, 50, 10));
+        assert_eq!(picture.lines().count(), 10);
+        assert!(picture.lines().all(|line| line.chars().count() == 50));
+    }
+
+    #[test]
+    fn test_pixels() {
+        let mut env = Environment::new(vec![2, 0]).unwrap();
+        env.rain(0.5).unwrap();
+
+        // The water stands 1 deep on the second column, the image spans the levels 0 to 2
+        let image = render_pixels(&env, 2, 4);
+        let pixel = |x: usize, y: usize| &image[(y * 2 + x) * 3..(y * 2 + x) * 3 + 3];
+        assert_eq!(image.len(), 2 * 4 * 3);
+        assert!((0..4).all(|y| pixel(0, y) == [139, 107, 74]));
+        assert_eq!(pixel(1, 1), [255, 255, 255]);
+        assert_eq!(pixel(1, 2), [20, 60, 140]);
+        assert!(render_png(&env, 2, 4).unwrap().starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,188 @@
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::EnvError;
+use crate::events::Event;
+use crate::number::Number;
+use crate::render;
+use crate::simulation::{Simulation, Snapshot};
+
+/// A recording of a `Simulation` which can be played back as an animation
+///
+/// Every `Snapshot` of the simulation is a frame. All the frames are drawn to the same scale, from the
+/// lowest column up to the highest water level of the whole simulation, so that the water can be seen
+/// rising from frame to frame.
+#[derive(Clone, Debug)]
+pub struct Replay<'a, T = f32> {
+    frames: &'a [Snapshot<T>],
+    events: &'a [Event],
+    extent: (f32, f32),
+}
+
+impl<'a, T: Number> Replay<'a, T> {
+    /// Constructs a new `Replay` of all the steps `simulation` has taken so far
+    pub fn new(simulation: &'a Simulation<T>) -> Self {
+        let frames = simulation.snapshots();
+        Self {
+            frames,
+            events: simulation.events(),
+            extent: frames
+                .iter()
+                .map(|frame| render::extent(&frame.columns))
+                .fold((0., 0.), |(bottom, top), (low, high)| {
+                    (bottom.min(low), top.max(high))
+                }),
+        }
+    }
+
+    /// Returns the recorded snapshots, one for every frame
+    pub fn frames(&self) -> &[Snapshot<T>] {
+        self.frames
+    }
+
+    /// Draws every frame like `render_terminal` under a line with its time and the events of its step
+    ///
+    /// `height` includes the line with the time.
+    pub fn terminal_frames(&self, width: usize, height: usize) -> Vec<String> {
+        let mut previous = None;
+        self.frames
+            .iter()
+            .map(|frame| {
+                let mut heading = format!("After {} hours", frame.time);
+                // The events of a step happened from the frame before on, until this one
+                let step = previous
+                    .replace(frame.time)
+                    .map_or(frame.time..frame.time, |start| start..frame.time);
+                for event in self
+                    .events
+                    .iter()
+                    .filter(|event| step.contains(&event.time))
+                {
+                    heading.push_str(&format!(", {}", event));
+                }
+                heading.truncate(width);
+                heading.push('\n');
+                heading
+                    + &render::terminal_frame(
+                        &frame.columns,
+                        width,
+                        height.saturating_sub(1),
+                        self.extent,
+                    )
+            })
+            .collect()
+    }
+
+    /// Plays the frames one after another in the terminal, waiting `delay` after every frame
+    pub fn play(
+        &self,
+        out: &mut dyn Write,
+        width: usize,
+        height: usize,
+        delay: Duration,
+    ) -> io::Result<()> {
+        for frame in self.terminal_frames(width, height) {
+            // Move to the top left corner and clear the screen before drawing the next frame
+            write!(out, "\x1b[H\x1b[2J{}", frame)?;
+            out.flush()?;
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+
+    /// Draws every frame like `render_png` as a PNG image of `width` by `height` pixels
+    pub fn png_frames(&self, width: u32, height: u32) -> Result<Vec<Vec<u8>>, EnvError> {
+        self.frames
+            .iter()
+            .map(|frame| render::encode_png(&self.pixels(frame, width, height), width, height))
+            .collect()
+    }
+
+    /// Draws all the frames into a single animated PNG which shows every frame for `delay`
+    ///
+    /// The animation plays in a loop.
+    pub fn apng(&self, width: u32, height: u32, delay: Duration) -> Result<Vec<u8>, EnvError> {
+        let mut encoded = vec![];
+        let mut encoder = png::Encoder::new(&mut encoded, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(self.frames.len() as u32, 0)
+            .map_err(io::Error::from)?;
+        let millis = delay.as_millis().min(u16::MAX as u128) as u16;
+        encoder
+            .set_frame_delay(millis, 1000)
+            .map_err(io::Error::from)?;
+
+        let mut writer = encoder.write_header().map_err(io::Error::from)?;
+        for frame in self.frames {
+            writer
+                .write_image_data(&self.pixels(frame, width, height))
+                .map_err(io::Error::from)?;
+        }
+        writer.finish().map_err(io::Error::from)?;
+        Ok(encoded)
+    }
+
+    fn pixels(&self, frame: &Snapshot<T>, width: u32, height: u32) -> Vec<u8> {
+        render::pixels_frame(&frame.columns, width, height, self.extent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Environment;
+
+    fn simulation() -> Simulation {
+        let mut simulation = Simulation::new(Environment::new(vec![3, 0, 1, 0, 3]).unwrap());
+        simulation.run(2., 0.5).unwrap();
+        simulation
+    }
+
+    #[test]
+    fn test_terminal_frames() {
+        let simulation = simulation();
+        let replay = Replay::new(&simulation);
+        let frames = replay.terminal_frames(80, 4);
+
+        assert_eq!(frames.len(), 5);
+        assert!(frames[0].starts_with("After 0 hours\n"));
+        // The pits fill up and merge over the peak after 0.4 hours, within the first step
+        assert!(frames[1].starts_with(
+            "After 0.5 hours, water standing on column 2, water standing on column 4"
+        ));
+        assert_eq!(frames[2].lines().next(), Some("After 1 hours"));
+        // Every frame is drawn to the same scale, 3 rows under the heading
+        assert!(frames.iter().all(|frame| frame.lines().count() == 4));
+    }
+
+    #[test]
+    fn test_png_frames() {
+        let simulation = simulation();
+        let replay = Replay::new(&simulation);
+        let frames = replay.png_frames(20, 10).unwrap();
+
+        assert_eq!(frames.len(), 5);
+        assert!(frames.iter().all(|frame| frame.starts_with(b"\x89PNG")));
+        assert_ne!(frames[0], frames[4]);
+    }
+
+    #[test]
+    fn test_apng() {
+        let simulation = simulation();
+        let animation = Replay::new(&simulation)
+            .apng(20, 10, Duration::from_millis(250))
+            .unwrap();
+
+        assert!(animation.starts_with(b"\x89PNG"));
+        assert_eq!(
+            animation
+                .windows(4)
+                .filter(|chunk| chunk == b"fcTL")
+                .count(),
+            5
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+use crate::boundary::Boundary;
+use crate::env::Environment;
+use crate::error::EnvError;
+use crate::schedule::RainSchedule;
+
+/// Everything needed to set up an `Environment` and rain on it, as it is read from JSON
+///
+/// Only the relief is required, the other fields default to unit widths, walls on both sides and no rain:
+///
+/// ```
+/// use water_level::Scenario;
+///
+/// let scenario = Scenario::from_json(
+///     r#"{
+///         "relief": [3, 1, 2],
+///         "right": {"height": 2.5},
+///         "rain": [{"duration": 2, "intensity": 1}]
+///     }"#,
+/// )
+/// .unwrap();
+/// let env = scenario.run().unwrap();
+///
+/// assert_eq!(env.water_level(2), 2.5);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Heights of the columns, from left to right
+    pub relief: Vec<f32>,
+    /// Widths of the columns, from left to right
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub widths: Option<Vec<f32>>,
+    #[serde(default)]
+    pub left: Boundary,
+    #[serde(default)]
+    pub right: Boundary,
+    #[serde(default)]
+    pub rain: RainSchedule,
+}
+
+impl Scenario {
+    /// Parses a `Scenario` from a JSON document
+    pub fn from_json(json: &str) -> Result<Self, EnvError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Constructs the dry `Environment` of the scenario
+    ///
+    /// Fails if the relief, the widths or the boundaries are not valid.
+    pub fn environment(&self) -> Result<Environment, EnvError> {
+        let mut env = Environment::from_heights(self.relief.clone())?;
+        if let Some(widths) = &self.widths {
+            env = env.with_widths(widths.clone())?;
+        }
+        env.with_boundaries(self.left, self.right)
+    }
+
+    /// Constructs the `Environment` of the scenario and rains on it
+    ///
+    /// Fails if the `Environment` cannot be constructed or the rain is not valid.
+    pub fn run(&self) -> Result<Environment, EnvError> {
+        let mut env = self.environment()?;
+        env.rain_schedule(&self.rain)?;
+        Ok(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_json() {
+        let scenario = Scenario::from_json(
+            r#"{
+                "relief": [3, 1, 2],
+                "widths": [1, 2, 1],
+                "left": "open",
+                "right": {"height": 4},
+                "rain": [{"duration": 1, "intensity": [0, 1, 0]}]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(scenario.relief, vec![3., 1., 2.]);
+        assert_eq!(scenario.widths, Some(vec![1., 2., 1.]));
+        assert_eq!(scenario.left, Boundary::Open);
+        assert_eq!(scenario.right, Boundary::Height(4.));
+        assert_eq!(
+            scenario.rain,
+            RainSchedule::new().then_profile(1., vec![0., 1., 0.])
+        );
+
+        let env = scenario.run().unwrap();
+        assert_eq!(env.water_level(2), 2.);
+        assert_eq!(env.water_volume(2), 2.);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let scenario = Scenario::from_json(r#"{"relief": [1, 2]}"#).unwrap();
+
+        assert_eq!(scenario.left, Boundary::Wall);
+        assert_eq!(scenario.rain, RainSchedule::new());
+        assert_eq!(
+            serde_json::to_string(&scenario).unwrap(),
+            r#"{"relief":[1.0,2.0],"left":"wall","right":"wall","rain":[]}"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        assert!(matches!(
+            Scenario::from_json(r#"{"relief": [1, 2], "hours": 2}"#),
+            Err(EnvError::Json(_))
+        ));
+        assert!(matches!(
+            Scenario::from_json(r#"{"relief": [1, "x"]}"#),
+            Err(EnvError::Json(_))
+        ));
+        assert!(matches!(
+            Scenario::from_json(r#"{"relief": []}"#)
+                .unwrap()
+                .environment(),
+            Err(EnvError::InvalidRelief(_))
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::EnvError;
+
+/// How hard it rains during a `Segment`, in units of rain per hour
+///
+/// In JSON the intensity is either a single number or an array with a number for every column.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Intensity {
+    /// The same intensity on every column
+    Uniform(f32),
+    /// A different intensity on every column of the relief, from left to right
+    PerColumn(Vec<f32>),
+}
+
+impl Intensity {
+    /// Returns the units of rain every column gets in `hours` hours
+    pub fn profile(&self, hours: f32, columns: usize) -> Vec<f32> {
+        match self {
+            Intensity::Uniform(intensity) => vec![intensity * hours; columns],
+            Intensity::PerColumn(intensities) => intensities
+                .iter()
+                .map(|intensity| intensity * hours)
+                .collect(),
+        }
+    }
+}
+
+/// A period of time with constant rain intensity
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    /// Length of the segment in hours
+    pub duration: f32,
+    pub intensity: Intensity,
+}
+
+/// A time-varying rain schedule, also known as a hyetograph
+///
+/// The schedule is a sequence of `Segments` which follow each other without gaps. A storm which starts
+/// light, peaks and then tapers off can be described as
+///
+/// ```
+/// use water_level::RainSchedule;
+///
+/// let storm = RainSchedule::new()
+///     .then(1., 0.5)
+///     .then(2., 2.)
+///     .then(1., 0.5);
+///
+/// assert_eq!(storm.duration(), 4.);
+/// assert_eq!(storm.total_rain(3), 15.);
+/// ```
+///
+/// In JSON the schedule is the array of its segments, like `[{"duration": 2, "intensity": 1}]`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RainSchedule {
+    segments: Vec<Segment>,
+}
+
+impl RainSchedule {
+    /// Constructs a new empty `RainSchedule`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a segment of `duration` hours with the same `intensity` on every column
+    pub fn then(self, duration: f32, intensity: f32) -> Self {
+        self.then_segment(Segment {
+            duration,
+            intensity: Intensity::Uniform(intensity),
+        })
+    }
+
+    /// Appends a segment of `duration` hours with a different intensity on every column
+    pub fn then_profile(self, duration: f32, intensities: Vec<f32>) -> Self {
+        self.then_segment(Segment {
+            duration,
+            intensity: Intensity::PerColumn(intensities),
+        })
+    }
+
+    /// Appends a segment to the end of the schedule
+    pub fn then_segment(mut self, segment: Segment) -> Self {
+        self.segments.push(segment);
+        self
+    }
+
+    /// Returns the segments of the schedule in order
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Returns the length of the whole schedule in hours
+    pub fn duration(&self) -> f32 {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+
+    /// Returns the units of rain that fall on a relief of `columns` columns during the whole schedule
+    pub fn total_rain(&self, columns: usize) -> f32 {
+        self.segments
+            .iter()
+            .flat_map(|segment| segment.intensity.profile(segment.duration, columns))
+            .sum()
+    }
+
+    /// Checks that every segment has a finite, non-negative duration and intensities and fits a relief of
+    /// `columns` columns
+    ///
+    /// The rain of a segment is checked as well, so a schedule that passes does not fail halfway through
+    /// because its intensity times its duration is too much to keep track of.
+    pub(crate) fn validate(&self, columns: usize) -> Result<(), EnvError> {
+        for segment in &self.segments {
+            if !segment.duration.is_finite() || segment.duration < 0. {
+                return Err(EnvError::InvalidStep(segment.duration));
+            }
+            let intensities = match &segment.intensity {
+                Intensity::Uniform(intensity) => std::slice::from_ref(intensity),
+                Intensity::PerColumn(intensities) => {
+                    if intensities.len() != columns {
+                        return Err(EnvError::ProfileLength {
+                            expected: columns,
+                            found: intensities.len(),
+                        });
+                    }
+                    intensities
+                }
+            };
+            if let Some(intensity) = intensities.iter().find(|intensity| {
+                !intensity.is_finite()
+                    || **intensity < 0.
+                    || !(**intensity * segment.duration).is_finite()
+            }) {
+                return Err(EnvError::InvalidRain(*intensity));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq as approx_eq;
+
+    #[test]
+    fn test_profile() {
+        assert_eq!(Intensity::Uniform(2.).profile(0.5, 3), vec![1., 1., 1.]);
+        assert_eq!(
+            Intensity::PerColumn(vec![1., 0., 4.]).profile(0.5, 3),
+            vec![0.5, 0., 2.]
+        );
+    }
+
+    #[test]
+    fn test_schedule() {
+        let schedule = RainSchedule::new()
+            .then(1., 1.)
+            .then_profile(0.5, vec![2., 4.]);
+
+        assert_eq!(schedule.segments().len(), 2);
+        approx_eq!(schedule.duration(), 1.5);
+        approx_eq!(schedule.total_rain(2), 5.);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(RainSchedule::new().then(1., 1.).validate(2).is_ok());
+        assert!(matches!(
+            RainSchedule::new().then(-1., 1.).validate(2),
+            Err(EnvError::InvalidStep(_))
+        ));
+        assert!(matches!(
+            RainSchedule::new().then_profile(1., vec![1.]).validate(2),
+            Err(EnvError::ProfileLength {
+                expected: 2,
+                found: 1
+            })
+        ));
+        assert!(matches!(
+            RainSchedule::new()
+                .then(1., 1.)
+                .then_profile(1., vec![1., f32::NAN])
+                .validate(2),
+            Err(EnvError::InvalidRain(_))
+        ));
+        assert!(matches!(
+            RainSchedule::new().then(1., -1.).validate(2),
+            Err(EnvError::InvalidRain(_))
+        ));
+        assert!(matches!(
+            RainSchedule::new().then(1e30, 1e30).validate(2),
+            Err(EnvError::InvalidRain(_))
+        ));
+    }
+}
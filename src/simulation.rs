@@ -0,0 +1,355 @@
+use serde::Serialize;
+
+use crate::env::{Column, Environment};
+use crate::error::EnvError;
+use crate::events::{Event, EventLog};
+use crate::number::Number;
+use crate::schedule::{Intensity, RainSchedule};
+
+/// The state of all the columns of the relief at a point in time
+#[derive(Clone, Debug, Serialize)]
+#[serde(bound = "T: Number + Serialize")]
+pub struct Snapshot<T = f32> {
+    /// Hours of rain since the start of the simulation
+    pub time: f32,
+    /// The columns of the relief, without the infinite sides
+    pub columns: Vec<Column<T>>,
+}
+
+impl<T: Number> Snapshot<T> {
+    /// Returns the water level of the column in position `pos`
+    ///
+    /// Positions are the same as in `Environment::water_level`.
+    pub fn water_level(&self, pos: usize) -> T {
+        self.columns[pos - 1].water_level()
+    }
+
+    /// Returns the depth of the water above the column in position `pos`
+    pub fn water_depth(&self, pos: usize) -> T {
+        self.columns[pos - 1].water()
+    }
+
+    /// Returns the units of water stored on the column in position `pos`
+    pub fn water_volume(&self, pos: usize) -> T {
+        self.columns[pos - 1].volume()
+    }
+}
+
+/// Time stepped simulation of the rain falling on an `Environment`
+///
+/// Instead of jumping straight to the endstate like `Environment::rain`, the simulation advances the
+/// `Environment` step by step and keeps a `Snapshot` of every intermediate state. Along the way it records
+/// the `Events` of the flood. The time is counted in `f32` hours whatever the `Number` type of the `Environment`.
+#[derive(Debug)]
+pub struct Simulation<T = f32> {
+    env: Environment<T>,
+    time: f32,
+    snapshots: Vec<Snapshot<T>>,
+    events: EventLog<T>,
+}
+
+impl<T: Number> Simulation<T> {
+    /// Constructs a new `Simulation` starting at time 0 with the current state of `env`
+    pub fn new(env: Environment<T>) -> Self {
+        let mut simulation = Self {
+            events: EventLog::new(&env),
+            env,
+            time: 0.,
+            snapshots: vec![],
+        };
+        simulation.snapshot();
+        simulation
+    }
+
+    /// Returns the `Environment` in its current state
+    pub fn environment(&self) -> &Environment<T> {
+        &self.env
+    }
+
+    /// Consumes the simulation and returns the `Environment` in its current state
+    pub fn into_environment(self) -> Environment<T> {
+        self.env
+    }
+
+    /// Returns the hours of rain since the start of the simulation
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Returns the snapshots of all the steps so far, starting with the initial state
+    pub fn snapshots(&self) -> &[Snapshot<T>] {
+        &self.snapshots
+    }
+
+    /// Returns the events of the flood so far, in order
+    ///
+    /// Every event is stamped with the hours of rain when it happened, which are found within its step.
+    pub fn events(&self) -> &[Event] {
+        self.events.events()
+    }
+
+    /// Advances the simulation by `hours` hours of rain and returns the new state
+    ///
+    /// Fails if `hours` is not a finite, positive number.
+    pub fn step(&mut self, hours: f32) -> Result<&Snapshot<T>, EnvError> {
+        self.step_with(hours, &Intensity::Uniform(1.))
+    }
+
+    /// Advances the simulation by `hours` hours of rain with the given `intensity` and returns the new state
+    ///
+    /// The losses of the `Environment` are applied after the rain of the step, so the water levels drop
+    /// during the steps without rain.
+    pub fn step_with(
+        &mut self,
+        hours: f32,
+        intensity: &Intensity,
+    ) -> Result<&Snapshot<T>, EnvError> {
+        if !hours.is_finite() || hours <= 0. {
+            return Err(EnvError::InvalidStep(hours));
+        }
+
+        // The events only need the state before the step, not the trace that grows with every step
+        let before = self.env.untraced();
+        let step = |env: &mut Environment<T>, hours: f32| -> Result<(), EnvError> {
+            let profile = intensity.profile(hours, env.len());
+            env.rain_profile(&profile.into_iter().map(T::from_f32).collect::<Vec<T>>())?;
+            env.apply_losses(hours)?;
+            Ok(())
+        };
+        step(&mut self.env, hours)?;
+        self.events
+            .record(&before, &self.env, self.time, hours, step)?;
+        self.time += hours;
+        Ok(self.snapshot())
+    }
+
+    /// Advances the simulation by `hours` hours of rain in steps of `step` hours
+    ///
+    /// The last step is shortened if `hours` is not a multiple of `step`. Returns the snapshots of the
+    /// new steps.
+    pub fn run(&mut self, hours: f32, step: f32) -> Result<&[Snapshot<T>], EnvError> {
+        if !step.is_finite() || step <= 0. {
+            return Err(EnvError::InvalidStep(step));
+        }
+        if !hours.is_finite() || hours < 0. {
+            return Err(EnvError::InvalidRain(hours));
+        }
+
+        let first = self.snapshots.len();
+        self.advance(hours, step, &Intensity::Uniform(1.))?;
+        Ok(&self.snapshots[first..])
+    }
+
+    /// Advances the simulation through the whole `schedule` in steps of at most `step` hours
+    ///
+    /// Every segment of the schedule is split into steps on its own, so a step never spans two segments.
+    /// Returns the snapshots of the new steps.
+    pub fn run_schedule(
+        &mut self,
+        schedule: &RainSchedule,
+        step: f32,
+    ) -> Result<&[Snapshot<T>], EnvError> {
+        if !step.is_finite() || step <= 0. {
+            return Err(EnvError::InvalidStep(step));
+        }
+        schedule.validate(self.env.len())?;
+
+        let first = self.snapshots.len();
+        for segment in schedule.segments() {
+            self.advance(segment.duration, step, &segment.intensity)?;
+        }
+        Ok(&self.snapshots[first..])
+    }
+
+    /// Returns the water level of the column in position `pos` for every snapshot as `(time, level)` pairs
+    pub fn fill_curve(&self, pos: usize) -> Vec<(f32, T)> {
+        self.snapshots
+            .iter()
+            .map(|snapshot| (snapshot.time, snapshot.water_level(pos)))
+            .collect()
+    }
+
+    /// Advances the simulation by `hours` hours of rain with the given `intensity` in steps of `step` hours
+    fn advance(&mut self, hours: f32, step: f32, intensity: &Intensity) -> Result<(), EnvError> {
+        let steps = (hours / step).ceil() as usize;
+        let mut elapsed = 0.;
+        for i in 1..=steps {
+            // Computed from the step count so the rounding errors do not pile up
+            let until = f32::min(i as f32 * step, hours);
+            if until > elapsed {
+                self.step_with(until - elapsed, intensity)?;
+            }
+            elapsed = until;
+        }
+        Ok(())
+    }
+
+    /// Stores the current state of the `Environment`
+    fn snapshot(&mut self) -> &Snapshot<T> {
+        self.snapshots.push(Snapshot {
+            time: self.time,
+            columns: self.env.columns().to_vec(),
+        });
+        &self.snapshots[self.snapshots.len() - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::Boundary;
+    use crate::events::EventKind;
+    use crate::losses::Losses;
+    use crate::number::Rational;
+    use assert_approx_eq::assert_approx_eq as approx_eq;
+
+    #[test]
+    fn test_initial_snapshot() {
+        let simulation = Simulation::new(Environment::new(vec![3, 1, 2]).unwrap());
+
+        assert_eq!(simulation.snapshots().len(), 1);
+        approx_eq!(simulation.time(), 0.);
+        approx_eq!(simulation.snapshots()[0].water_level(2), 1.);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut simulation = Simulation::new(Environment::new(vec![4, 1, 4]).unwrap());
+
+        let snapshot = simulation.step(0.5).unwrap();
+        approx_eq!(snapshot.time, 0.5);
+        approx_eq!(snapshot.water_level(2), 2.5);
+
+        let snapshot = simulation.step(0.5).unwrap();
+        approx_eq!(snapshot.time, 1.);
+        approx_eq!(snapshot.water_level(2), 4.);
+        approx_eq!(snapshot.water_depth(2), 3.);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut simulation = Simulation::new(Environment::new(vec![3, 7, 4, 5, 3]).unwrap());
+
+        let snapshots = simulation.run(2., 0.75).unwrap();
+        assert_eq!(snapshots.len(), 3);
+        approx_eq!(snapshots[2].time, 2.);
+
+        approx_eq!(simulation.time(), 2.);
+        approx_eq!(simulation.environment().water_level(1), 6.);
+        approx_eq!(simulation.environment().water_level(2), 7.);
+        approx_eq!(simulation.environment().water_level(5), 6.3333333);
+        approx_eq!(
+            simulation.environment().mass_balance().total_stored(),
+            10.,
+            1e-4
+        );
+    }
+
+    #[test]
+    fn test_exact_simulation() {
+        let env = Environment::<Rational>::from_relief(vec![4, 1, 4]).unwrap();
+        let mut simulation = Simulation::new(env);
+        simulation.run(1., 0.25).unwrap();
+
+        assert_eq!(
+            simulation.snapshots()[1].water_level(2),
+            Rational::new(7, 4)
+        );
+        assert_eq!(simulation.environment().water_level(2), Rational::from(4));
+        assert_eq!(simulation.events()[0].kind, EventKind::BasinStarted);
+    }
+
+    #[test]
+    fn test_fill_curve() {
+        let mut simulation = Simulation::new(Environment::new(vec![4, 1, 4]).unwrap());
+        simulation.run(2., 1.).unwrap();
+
+        let curve = simulation.fill_curve(2);
+        assert_eq!(curve.len(), 3);
+        approx_eq!(curve[0].1, 1.);
+        approx_eq!(curve[1].1, 4.);
+        approx_eq!(curve[2].1, 5.);
+        approx_eq!(curve[2].0, 2.);
+    }
+
+    #[test]
+    fn test_run_schedule() {
+        let mut simulation = Simulation::new(Environment::new(vec![4, 1, 4]).unwrap());
+        let schedule = RainSchedule::new()
+            .then(1., 0.5)
+            .then(0.5, 2.)
+            .then_profile(1., vec![0., 0.5, 0.]);
+
+        let snapshots = simulation.run_schedule(&schedule, 0.5).unwrap();
+        assert_eq!(snapshots.len(), 5);
+        approx_eq!(snapshots[1].time, 1.);
+        approx_eq!(snapshots[1].water_level(2), 2.5);
+        approx_eq!(snapshots[2].time, 1.5);
+        approx_eq!(snapshots[2].water_level(2), 4.5);
+        approx_eq!(snapshots[4].time, 2.5);
+        approx_eq!(snapshots[4].water_level(2), 4.6666667);
+    }
+
+    #[test]
+    fn test_water_drops_between_storms() {
+        let mut env = Environment::new(vec![4, 1, 4]).unwrap();
+        env.set_losses(Losses::new(0.25)).unwrap();
+        let mut simulation = Simulation::new(env);
+        let schedule = RainSchedule::new().then(1., 1.).then(2., 0.);
+
+        let snapshots = simulation.run_schedule(&schedule, 1.).unwrap();
+        approx_eq!(snapshots[0].water_level(2), 3.75);
+        approx_eq!(snapshots[1].water_level(2), 3.5);
+        approx_eq!(snapshots[2].water_level(2), 3.25);
+    }
+
+    #[test]
+    fn test_invalid_step() {
+        let mut simulation = Simulation::new(Environment::new(vec![3, 1, 2]).unwrap());
+
+        assert!(matches!(simulation.step(0.), Err(EnvError::InvalidStep(_))));
+        assert!(matches!(
+            simulation.run(1., -1.),
+            Err(EnvError::InvalidStep(_))
+        ));
+        assert_eq!(simulation.snapshots().len(), 1);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut simulation = Simulation::new(Environment::new(vec![5, 1, 3, 1, 5]).unwrap());
+        simulation.run(1., 0.25).unwrap();
+
+        // Both pits fill up to the peak and merge over it after 0.8 hours, during the step up to 1 hour
+        let onset = |kind: EventKind, start: usize, end: usize| {
+            simulation
+                .events()
+                .iter()
+                .find(|event| (event.kind, event.start, event.end) == (kind, start, end))
+                .map(|event| event.time)
+                .unwrap()
+        };
+        approx_eq!(onset(EventKind::BasinStarted, 2, 2), 0.);
+        approx_eq!(onset(EventKind::BasinStarted, 4, 4), 0.);
+        approx_eq!(onset(EventKind::BasinsMerged, 2, 4), 0.8);
+        approx_eq!(onset(EventKind::PeakSubmerged, 3, 3), 0.8);
+    }
+
+    #[test]
+    fn test_basin_full_event() {
+        let env = Environment::new(vec![3, 1, 5])
+            .unwrap()
+            .with_boundaries(Boundary::Open, Boundary::Wall)
+            .unwrap();
+        let mut simulation = Simulation::new(env);
+        simulation.run(2., 0.5).unwrap();
+
+        let full = simulation
+            .events()
+            .iter()
+            .filter(|event| event.kind == EventKind::BasinFull)
+            .collect::<Vec<&Event>>();
+        assert_eq!(full.len(), 1);
+        assert_eq!((full[0].start, full[0].end), (2, 2));
+    }
+}
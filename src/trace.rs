@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+/// The part of the flow algorithm that has made a decision
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Handler {
+    /// Water has reached the left side of the relief
+    LeftSide,
+    /// Water has reached the right side of the relief
+    RightSide,
+    /// No water to place, the walk jumps to the next column with rain
+    Dry,
+    /// A column lower than both of its neighbours
+    Valley,
+    /// A column higher than both of its neighbours
+    Peak,
+    /// A column higher than the next one and not lower than the one before
+    Downwards,
+    /// A run of equal columns after a step up
+    SPlateau,
+    /// A run of equal columns after a step down
+    LPlateau,
+    /// A column between a lower and a higher one
+    Upwards,
+    /// A column level with the one before and not higher than the next
+    Level,
+}
+
+/// What the flow algorithm does after a `Handler` has made its decision
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// The water is returned to the column it came from
+    Return { backwater: f32 },
+    /// The water flows on into the column at position `to`
+    Flow { to: usize, water: f32 },
+    /// The water flows into the column at position `to`, and once its backwater returns the algorithm
+    /// resumes with `then`
+    Call { to: usize, water: f32, then: Resume },
+    /// The water has left the relief over a side
+    Spill { water: f32 },
+}
+
+/// How the flow algorithm resumes after the backwater of an `Action::Call` has returned
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "resume", rename_all = "snake_case")]
+pub enum Resume {
+    /// The backwater is returned together with `water`
+    Add { water: f32 },
+    /// The backwater flows into the column at position `pos`
+    Flow { pos: usize },
+    /// The backwater flows into the column at position `pos`, if there is any
+    FlowIfAny { pos: usize },
+    /// The backwater runs back down to the last column from position `start` up to `end` that is lower than
+    /// the column before it, and on from there, if there is any backwater and such a column
+    RunBack { start: usize, end: usize },
+}
+
+/// A single decision of the flow algorithm
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct TraceStep {
+    pub handler: Handler,
+    /// Position of the column the decision was made at
+    pub pos: usize,
+    /// Units of water flowing into the column, including the rain falling on it
+    pub rain_water: f32,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// The decisions the flow algorithm of an `Environment` has made while tracing was on
+///
+/// Tracing is switched on with `Environment::start_trace`. The trace can be exported as JSON to reproduce
+/// the exact decision path of a rain, for example in a bug report.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Trace {
+    steps: Vec<TraceStep>,
+}
+
+impl Trace {
+    /// Returns the decisions in the order they were made
+    pub fn steps(&self) -> &[TraceStep] {
+        &self.steps
+    }
+
+    /// Returns the decisions as a JSON array, one object for each
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a trace is always valid JSON")
+    }
+
+    pub(crate) fn push(&mut self, step: TraceStep) {
+        self.steps.push(step);
+    }
+}